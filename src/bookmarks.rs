@@ -0,0 +1,211 @@
+use std::fs;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use druid::piet::{ImageFormat, InterpolationMode};
+use druid::widget::{Button, Flex, Label, List, Painter, Scroll, TextBox};
+use druid::{Data, ExtEventSink, Lens, Size, Target, Widget, WidgetExt};
+
+use crate::config::config_path;
+use crate::delegate::{ADD_BOOKMARK, GOTO_LOCATION, REMOVE_BOOKMARK, THUMBNAIL_READY};
+use crate::fractal::RenderParams;
+use crate::fractal_widget::FractalData;
+use crate::image_utils::RGB;
+use crate::location::Location;
+use crate::renderer::Renderer;
+
+const FILE_NAME: &str = "bookmarks.fractal";
+// Thumbnail side, in pixels
+const THUMBNAIL: usize = 64;
+
+#[derive(Clone, Data, Lens)]
+pub struct Bookmark {
+    pub name: String,
+    pub location: Location,
+    // Rgba thumbnail data, empty until rendered
+    pub thumbnail: Arc<Vec<u8>>,
+}
+
+impl Bookmark {
+    pub fn new(name: String, location: Location) -> Self {
+        Bookmark {
+            name: name,
+            location: location,
+            thumbnail: Arc::new(Vec::new()),
+        }
+    }
+}
+
+fn render_thumbnail(location: &Location, renderer: &mut Renderer) -> Arc<Vec<u8>> {
+    let mut params = RenderParams::new();
+    location.apply(&mut params);
+    let size = Size::new(THUMBNAIL as f64, THUMBNAIL as f64);
+    let image = renderer.render(size, location.view(), &params);
+    let mut thumbnail = Vec::new();
+    RGB::create_image_data(&image, &mut thumbnail);
+    Arc::new(thumbnail)
+}
+
+/// Renders bookmark thumbnails off the UI thread, one at a time with a
+/// shared renderer created on the first request, and sends them back with
+/// `THUMBNAIL_READY`
+pub struct Thumbnails {
+    sink: ExtEventSink,
+    // Rendering thread, None to render inline where threads are unavailable
+    sender: Option<Sender<Location>>,
+    renderer: Option<Renderer>,
+}
+
+impl Thumbnails {
+    pub fn new(sink: ExtEventSink) -> Self {
+        let (sender, receiver) = mpsc::channel::<Location>();
+        let thread_sink = sink.clone();
+        let thread = thread::Builder::new().spawn(move || {
+            let mut renderer = None;
+            for location in receiver {
+                let renderer = renderer.get_or_insert_with(Renderer::new);
+                let thumbnail = render_thumbnail(&location, renderer);
+                let res = thread_sink.submit_command(
+                    THUMBNAIL_READY,
+                    (location, thumbnail),
+                    Target::Auto,
+                );
+                if res.is_err() {
+                    // The app has quit
+                    break;
+                }
+            }
+        });
+        Thumbnails {
+            sink: sink,
+            sender: thread.ok().map(|_| sender),
+            renderer: None,
+        }
+    }
+
+    pub fn request(&mut self, location: Location) {
+        if let Some(sender) = &self.sender {
+            if sender.send(location).is_err() {
+                println!("Thumbnail renderer stopped");
+            }
+        } else {
+            let renderer = self.renderer.get_or_insert_with(Renderer::new);
+            let thumbnail = render_thumbnail(&location, renderer);
+            let res =
+                self.sink
+                    .submit_command(THUMBNAIL_READY, (location, thumbnail), Target::Auto);
+            if let Err(err) = res {
+                println!("Failed to send thumbnail: {}", err);
+            }
+        }
+    }
+}
+
+/// Whether a name fits on a `[name]` section line of the bookmarks file
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c == '[' || c == ']' || c.is_control())
+}
+
+/// Load the bookmarks file, without thumbnails
+pub fn load() -> Vec<Bookmark> {
+    let text = match config_path(FILE_NAME).and_then(|path| fs::read_to_string(path).ok()) {
+        Some(text) => text,
+        None => return Vec::new(),
+    };
+    // Sections of location text, each headed by a [name] line
+    let mut sections: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            let name = trimmed[1..trimmed.len() - 1].to_string();
+            sections.push((name, String::new()));
+        } else if let Some((_, body)) = sections.last_mut() {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+    if sections.is_empty() {
+        return Vec::new();
+    }
+    sections
+        .into_iter()
+        .filter_map(|(name, body)| match Location::from_text(&body) {
+            Ok(location) => Some(Bookmark::new(name, location)),
+            Err(err) => {
+                println!("Skipping bookmark {}: {}", name, err);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn save(bookmarks: &[Bookmark]) {
+    let path = match config_path(FILE_NAME) {
+        Some(path) => path,
+        None => return,
+    };
+    let text: String = bookmarks
+        .iter()
+        .map(|bookmark| format!("[{}]\n{}\n", bookmark.name, bookmark.location.to_text()))
+        .collect();
+    let res = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, text));
+    if let Err(err) = res {
+        println!("Failed to save {}: {}", path.display(), err);
+    }
+}
+
+fn build_row() -> impl Widget<Bookmark> {
+    let thumbnail = Painter::new(|ctx, bookmark: &Bookmark, _env| {
+        if bookmark.thumbnail.is_empty() {
+            return;
+        }
+        let image = ctx.make_image(
+            THUMBNAIL,
+            THUMBNAIL,
+            &bookmark.thumbnail,
+            ImageFormat::RgbaSeparate,
+        );
+        if let Ok(image) = image {
+            let rect = ctx.size().to_rect();
+            ctx.draw_image(&image, rect, InterpolationMode::Bilinear);
+        }
+    })
+    .fix_size(THUMBNAIL as f64, THUMBNAIL as f64);
+    let name = Label::raw().lens(Bookmark::name);
+    let view = Flex::row()
+        .with_child(thumbnail)
+        .with_spacer(5.)
+        .with_child(name)
+        .on_click(|ctx, bookmark: &mut Bookmark, _env| {
+            ctx.submit_command(GOTO_LOCATION.with(bookmark.location.clone()));
+        });
+    let remove = Button::new("x").on_click(|ctx, bookmark: &mut Bookmark, _env| {
+        ctx.submit_command(REMOVE_BOOKMARK.with(bookmark.clone()));
+    });
+    Flex::row()
+        .with_flex_child(view, 1.)
+        .with_child(remove)
+        .padding(5.)
+}
+
+pub fn build_panel() -> impl Widget<FractalData> {
+    let name = TextBox::new()
+        .with_placeholder("name")
+        .lens(FractalData::bookmark_name);
+    let add = Button::<FractalData>::new("add").on_click(|ctx, _data, _env| {
+        ctx.submit_command(ADD_BOOKMARK);
+    });
+    let list = Scroll::new(List::new(build_row).lens(FractalData::bookmarks)).vertical();
+    Flex::column()
+        .with_child(
+            Flex::row()
+                .with_flex_child(name.expand_width(), 1.)
+                .with_child(add),
+        )
+        .with_flex_child(list, 1.)
+        .padding(5.)
+}
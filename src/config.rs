@@ -0,0 +1,11 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Path of a file in the user configuration directory
+pub fn config_path(name: &str) -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("fractal").join(name))
+}
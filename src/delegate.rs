@@ -0,0 +1,142 @@
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+
+use druid::{
+    commands, AppDelegate, Command, DelegateCtx, Env, ExtEventSink, Handled, Selector, Target,
+};
+
+use crate::bookmarks::{Bookmark, Thumbnails};
+use crate::fractal::{feature_stretch, RenderParams, TrapShape};
+use crate::fractal_widget::FractalData;
+use crate::image_utils::Texture;
+use crate::kfr;
+use crate::location::Location;
+use crate::viewport::{Mat2, Viewport};
+
+/// Restore a location
+pub const GOTO_LOCATION: Selector<Location> = Selector::new("fractal.goto-location");
+pub const ADD_BOOKMARK: Selector = Selector::new("fractal.add-bookmark");
+pub const REMOVE_BOOKMARK: Selector<Bookmark> = Selector::new("fractal.remove-bookmark");
+/// Rendered thumbnail of the bookmarks at a location
+pub const THUMBNAIL_READY: Selector<(Location, Arc<Vec<u8>>)> =
+    Selector::new("fractal.thumbnail-ready");
+/// Estimate the skew of the current view in the background
+pub const UNSKEW: Selector = Selector::new("fractal.unskew");
+/// Estimated skew of the view it was requested for
+pub const SKEW_READY: Selector<(Viewport, Mat2)> = Selector::new("fractal.skew-ready");
+
+pub struct Delegate {
+    sink: ExtEventSink,
+    thumbnails: Thumbnails,
+}
+
+impl Delegate {
+    /// Start rendering the thumbnails of the loaded bookmarks
+    pub fn new(sink: ExtEventSink, mut thumbnails: Thumbnails, bookmarks: &[Bookmark]) -> Self {
+        for bookmark in bookmarks {
+            thumbnails.request(bookmark.location.clone());
+        }
+        Delegate {
+            sink: sink,
+            thumbnails: thumbnails,
+        }
+    }
+}
+
+fn estimate_skew(view: &Viewport, params: &RenderParams) -> Mat2 {
+    let focus = view.focus;
+    let max_iter = params.iterations.max_iter(focus.radius);
+    feature_stretch(focus.center, focus.radius, max_iter, params)
+}
+
+impl AppDelegate<FractalData> for Delegate {
+    fn command(
+        &mut self,
+        _ctx: &mut DelegateCtx,
+        _target: Target,
+        cmd: &Command,
+        data: &mut FractalData,
+        _env: &Env,
+    ) -> Handled {
+        if let Some(location) = cmd.get(GOTO_LOCATION) {
+            data.set_location(location);
+            return Handled::Yes;
+        }
+        if cmd.is(ADD_BOOKMARK) {
+            if let Some(location) = data.add_bookmark() {
+                self.thumbnails.request(location);
+            }
+            return Handled::Yes;
+        }
+        if cmd.is(UNSKEW) {
+            let (view, params) = (data.view(), data.params.clone());
+            let sink = self.sink.clone();
+            let thread = thread::Builder::new().spawn(move || {
+                let skew = estimate_skew(&view, &params);
+                let _ = sink.submit_command(SKEW_READY, (view, skew), Target::Auto);
+            });
+            if thread.is_err() {
+                // No threads, block instead
+                let view = data.view();
+                let skew = estimate_skew(&view, &data.params);
+                data.finish_unskew(&view, skew);
+            }
+            return Handled::Yes;
+        }
+        if let Some((view, skew)) = cmd.get(SKEW_READY) {
+            data.finish_unskew(view, *skew);
+            return Handled::Yes;
+        }
+        if let Some((location, thumbnail)) = cmd.get(THUMBNAIL_READY) {
+            data.set_thumbnail(location, thumbnail);
+            return Handled::Yes;
+        }
+        if let Some(bookmark) = cmd.get(REMOVE_BOOKMARK) {
+            data.remove_bookmark(bookmark);
+            return Handled::Yes;
+        }
+        if let Some(file_info) = cmd.get(commands::OPEN_FILE) {
+            let path = file_info.path();
+            let extension = path.extension().and_then(|ext| ext.to_str());
+            if let Some(ext @ ("fractal" | "kfr")) = extension {
+                let location = fs::read_to_string(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|text| match ext {
+                        "kfr" => kfr::parse(&text),
+                        _ => Location::from_text(&text),
+                    });
+                match location {
+                    Ok(location) => data.set_location(&location),
+                    Err(err) => println!("Failed to load {}: {}", path.display(), err),
+                }
+                return Handled::Yes;
+            }
+            let texture = fs::read(path)
+                .ok()
+                .and_then(|bytes| Texture::from_ppm(&bytes));
+            match texture {
+                Some(texture) => {
+                    data.params.trap.texture = Some(Arc::new(texture));
+                    data.params.trap.shape = TrapShape::Texture;
+                }
+                None => println!("Failed to load {}", path.display()),
+            }
+            return Handled::Yes;
+        }
+        if let Some(file_info) = cmd.get(commands::SAVE_FILE_AS) {
+            let path = file_info.path();
+            let location = data.location();
+            let text = if path.extension().map_or(false, |ext| ext == "kfr") {
+                kfr::write(&location)
+            } else {
+                location.to_text()
+            };
+            if let Err(err) = fs::write(path, text) {
+                println!("Failed to save {}: {}", path.display(), err);
+            }
+            return Handled::Yes;
+        }
+        Handled::No
+    }
+}
@@ -1,7 +1,14 @@
-use druid::{kurbo::Circle, Size};
+use std::f64::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
 
-use crate::image_utils::{FPoint, IPoint, RGB};
+use druid::{Data, Lens, Point, Size};
 
+use crate::image_utils::{FPoint, IPoint, Texture, RGB};
+use crate::palette::Palette;
+use crate::viewport::{Mat2, Viewport};
+
+#[derive(Clone, Copy, Default)]
 pub struct Complex {
     pub r: f64,
     pub i: f64,
@@ -11,66 +18,953 @@ impl Complex {
     pub fn zero() -> Self {
         Complex { r: 0., i: 0. }
     }
+
+    pub fn one() -> Self {
+        Complex { r: 1., i: 0. }
+    }
+
+    pub fn norm_sqr(&self) -> f64 {
+        self.r * self.r + self.i * self.i
+    }
+
+    pub fn scale(&self, k: f64) -> Self {
+        Complex {
+            r: self.r * k,
+            i: self.i * k,
+        }
+    }
 }
 
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex {
+            r: self.r + other.r,
+            i: self.i + other.i,
+        }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex {
+            r: self.r - other.r,
+            i: self.i - other.i,
+        }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            r: self.r * other.r - self.i * other.i,
+            i: self.r * other.i + self.i * other.r,
+        }
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let n = other.norm_sqr();
+        Complex {
+            r: (self.r * other.r + self.i * other.i) / n,
+            i: (self.i * other.r - self.r * other.i) / n,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
 pub struct ConvResult {
     max_iter: usize,
     iter: usize,
+    escaped: bool,
+    norm_sqr: f64,
+    // Exterior distance estimate, in world units
+    distance: f64,
+    // Unit normal of the potential level set
+    normal: Complex,
+    // Orbit trap statistics
+    trap_min: f64,
+    trap_avg: f64,
+    trap_pos: Complex,
+    // Period of the attracting cycle, 0 if none was detected
+    period: usize,
+    // Iteration of the smallest |z|
+    atom_period: usize,
+    // Interior distance estimate, in world units
+    interior_distance: f64,
+    // Last and previous orbit averages
+    tia: (f64, f64),
+    stripe: (f64, f64),
+}
+
+/// The parts of a `ConvResult` that `color_scheme` reads, compact enough to
+/// keep for every pixel and recolor without iterating again
+#[derive(Clone, Copy, Default)]
+pub struct ColorData {
+    iter: u32,
+    max_iter: u32,
+    period: u32,
+    atom_period: u32,
     norm_sqr: f64,
+    // Exterior distance estimate if escaped, interior one otherwise, in pixels
+    distance: f32,
+    escaped: bool,
+    normal: [f32; 2],
+    // Trap min, average and closest point, or the last and previous orbit
+    // averages, depending on the color mode
+    stats: [f32; 4],
 }
 
-pub fn color_scheme(res: &ConvResult) -> RGB {
-    if res.iter == res.max_iter {
-        return RGB::BLACK;
+impl ColorData {
+    pub fn new(res: &ConvResult, pixel_size: f64, mode: ColorMode) -> Self {
+        let distance = if res.escaped {
+            res.distance
+        } else {
+            res.interior_distance
+        };
+        let stats = match mode {
+            ColorMode::TriangleInequality => [res.tia.0, res.tia.1, 0., 0.],
+            ColorMode::Stripe => [res.stripe.0, res.stripe.1, 0., 0.],
+            _ => [res.trap_min, res.trap_avg, res.trap_pos.r, res.trap_pos.i],
+        };
+        ColorData {
+            iter: res.iter as u32,
+            max_iter: res.max_iter as u32,
+            period: res.period as u32,
+            atom_period: res.atom_period as u32,
+            norm_sqr: res.norm_sqr,
+            distance: (distance / pixel_size) as f32,
+            escaped: res.escaped,
+            normal: [res.normal.r as f32, res.normal.i as f32],
+            stats: stats.map(|v| v as f32),
+        }
     }
-    let l = 1. / 2_f64.log2();
-    let v = 5. + res.iter as f64 - (0.5_f64.log2() - res.norm_sqr.log2().log2()) * l;
-    let mut res = RGB::from_hsv(
-        360. * v / res.max_iter as f64,
-        1.,
-        10. * v / res.max_iter as f64,
+}
+
+impl ConvResult {
+    pub fn iter(&self) -> usize {
+        self.iter
+    }
+
+    pub fn escaped(&self) -> bool {
+        self.escaped
+    }
+
+    /// Final |z|
+    pub fn norm(&self) -> f64 {
+        self.norm_sqr.sqrt()
+    }
+
+    /// Period of the attracting cycle, 0 if none was detected
+    pub fn period(&self) -> usize {
+        self.period
+    }
+}
+
+/// Coloring algorithm applied to the escaped points
+#[derive(Clone, Copy, PartialEq, Data)]
+pub enum ColorMode {
+    /// Smooth iteration count
+    Smooth,
+    /// Smooth iteration count, darkened along the boundary by the distance estimate
+    Distance,
+    /// Anti-aliased black boundary on a white background
+    LineArt,
+    /// Minimum orbit distance to the trap
+    TrapMin,
+    /// Average orbit distance to the trap
+    TrapAverage,
+    /// Position of the orbit point closest to the trap
+    TrapPosition,
+    /// Triangle inequality average
+    TriangleInequality,
+    /// Stripe average
+    Stripe,
+}
+
+impl ColorMode {
+    pub fn next(&self) -> Self {
+        match self {
+            ColorMode::Smooth => ColorMode::Distance,
+            ColorMode::Distance => ColorMode::LineArt,
+            ColorMode::LineArt => ColorMode::TrapMin,
+            ColorMode::TrapMin => ColorMode::TrapAverage,
+            ColorMode::TrapAverage => ColorMode::TrapPosition,
+            ColorMode::TrapPosition => ColorMode::TriangleInequality,
+            ColorMode::TriangleInequality => ColorMode::Stripe,
+            ColorMode::Stripe => ColorMode::Smooth,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMode::Smooth => "smooth",
+            ColorMode::Distance => "distance",
+            ColorMode::LineArt => "line art",
+            ColorMode::TrapMin => "trap min",
+            ColorMode::TrapAverage => "trap average",
+            ColorMode::TrapPosition => "trap position",
+            ColorMode::TriangleInequality => "triangle inequality",
+            ColorMode::Stripe => "stripe",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "smooth" => Some(ColorMode::Smooth),
+            "distance" => Some(ColorMode::Distance),
+            "line art" => Some(ColorMode::LineArt),
+            "trap min" => Some(ColorMode::TrapMin),
+            "trap average" => Some(ColorMode::TrapAverage),
+            "trap position" => Some(ColorMode::TrapPosition),
+            "triangle inequality" => Some(ColorMode::TriangleInequality),
+            "stripe" => Some(ColorMode::Stripe),
+            _ => None,
+        }
+    }
+
+    fn uses_trap(&self) -> bool {
+        matches!(
+            self,
+            ColorMode::TrapMin | ColorMode::TrapAverage | ColorMode::TrapPosition
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Data)]
+pub enum TrapShape {
+    Point,
+    Line,
+    Cross,
+    Circle,
+    /// Textured square, black texels are transparent
+    Texture,
+}
+
+impl TrapShape {
+    pub fn next(&self) -> Self {
+        match self {
+            TrapShape::Point => TrapShape::Line,
+            TrapShape::Line => TrapShape::Cross,
+            TrapShape::Cross => TrapShape::Circle,
+            TrapShape::Circle => TrapShape::Texture,
+            TrapShape::Texture => TrapShape::Point,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrapShape::Point => "point",
+            TrapShape::Line => "line",
+            TrapShape::Cross => "cross",
+            TrapShape::Circle => "circle",
+            TrapShape::Texture => "texture",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "point" => Some(TrapShape::Point),
+            "line" => Some(TrapShape::Line),
+            "cross" => Some(TrapShape::Cross),
+            "circle" => Some(TrapShape::Circle),
+            "texture" => Some(TrapShape::Texture),
+            _ => None,
+        }
+    }
+}
+
+/// Coloring algorithm applied to the points that didn't escape
+#[derive(Clone, Copy, PartialEq, Data)]
+pub enum InteriorMode {
+    Black,
+    /// Final |z|
+    Norm,
+    /// Period of the attracting cycle
+    Period,
+    /// Interior distance estimate
+    Distance,
+    /// Iteration reaching the smallest |z|
+    AtomDomain,
+}
+
+impl InteriorMode {
+    pub fn next(&self) -> Self {
+        match self {
+            InteriorMode::Black => InteriorMode::Norm,
+            InteriorMode::Norm => InteriorMode::Period,
+            InteriorMode::Period => InteriorMode::Distance,
+            InteriorMode::Distance => InteriorMode::AtomDomain,
+            InteriorMode::AtomDomain => InteriorMode::Black,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InteriorMode::Black => "black",
+            InteriorMode::Norm => "norm",
+            InteriorMode::Period => "period",
+            InteriorMode::Distance => "distance",
+            InteriorMode::AtomDomain => "atom domain",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "black" => Some(InteriorMode::Black),
+            "norm" => Some(InteriorMode::Norm),
+            "period" => Some(InteriorMode::Period),
+            "distance" => Some(InteriorMode::Distance),
+            "atom domain" => Some(InteriorMode::AtomDomain),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Data, Lens)]
+pub struct OrbitTrap {
+    pub shape: TrapShape,
+    pub center: Point,
+    /// Line and cross orientation, in degrees
+    pub angle: f64,
+    /// Circle radius, texture half size and distance scale
+    pub radius: f64,
+    pub texture: Option<Arc<Texture>>,
+}
+
+// Textures are compared by identity, not texel by texel
+impl PartialEq for OrbitTrap {
+    fn eq(&self, other: &Self) -> bool {
+        let same_texture = match (&self.texture, &other.texture) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.shape == other.shape
+            && self.center == other.center
+            && self.angle == other.angle
+            && self.radius == other.radius
+            && same_texture
+    }
+}
+
+impl OrbitTrap {
+    pub fn new() -> Self {
+        OrbitTrap {
+            shape: TrapShape::Point,
+            center: Point::ZERO,
+            angle: 0.,
+            radius: 0.5,
+            texture: None,
+        }
+    }
+
+    // Texture coordinates of a trap-relative point
+    fn uv(&self, p: &Complex) -> (f64, f64) {
+        (
+            p.r / (2. * self.radius) + 0.5,
+            p.i / (2. * self.radius) + 0.5,
+        )
+    }
+
+    fn distance(&self, z: &Complex) -> f64 {
+        let p = Complex {
+            r: z.r - self.center.x,
+            i: z.i - self.center.y,
+        };
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        match self.shape {
+            TrapShape::Point => p.norm_sqr().sqrt(),
+            TrapShape::Line => (p.r * sin - p.i * cos).abs(),
+            TrapShape::Cross => (p.r * sin - p.i * cos)
+                .abs()
+                .min((p.r * cos + p.i * sin).abs()),
+            TrapShape::Circle => (p.norm_sqr().sqrt() - self.radius).abs(),
+            TrapShape::Texture => {
+                let dx = (p.r.abs() - self.radius).max(0.);
+                let dy = (p.i.abs() - self.radius).max(0.);
+                if dx > 0. || dy > 0. {
+                    return (dx * dx + dy * dy).sqrt();
+                }
+                let (u, v) = self.uv(&p);
+                match &self.texture {
+                    Some(texture) if texture.sample(u, v) == RGB::BLACK => self.radius,
+                    _ => 0.,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Data)]
+pub enum Formula {
+    Mandelbrot,
+    /// Julia set of the constant `RenderParams::julia_c`
+    Julia,
+}
+
+impl Formula {
+    pub fn next(&self) -> Self {
+        match self {
+            Formula::Mandelbrot => Formula::Julia,
+            Formula::Julia => Formula::Mandelbrot,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Formula::Mandelbrot => "mandelbrot",
+            Formula::Julia => "julia",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mandelbrot" => Some(Formula::Mandelbrot),
+            "julia" => Some(Formula::Julia),
+            _ => None,
+        }
+    }
+
+    /// Initial z and constant c of the iteration at a point
+    fn start(&self, point: Complex, julia_c: &Point) -> (Complex, Complex) {
+        match self {
+            Formula::Mandelbrot => (Complex::zero(), point),
+            Formula::Julia => (
+                point,
+                Complex {
+                    r: julia_c.x,
+                    i: julia_c.y,
+                },
+            ),
+        }
+    }
+}
+
+/// Mapping from image pixels to the complex plane
+#[derive(Clone, Copy, PartialEq, Data)]
+pub enum Projection {
+    /// Regular view of the focus circle
+    Linear,
+    /// Log-polar strip around the focus center, see `exp_map_to_world`
+    ExpMap,
+}
+
+impl Projection {
+    /// World point of a pixel, with the local world size of a pixel and the
+    /// radius of the view it belongs to
+    pub fn map(&self, view: &Viewport, px_size: &Size, point: &IPoint) -> (FPoint, f64, f64) {
+        match self {
+            Projection::Linear => {
+                let world = view.px_to_world(*px_size, Point::new(point.x as f64, point.y as f64));
+                let world = FPoint::new(world.x, world.y);
+                (world, view.pixel_size(*px_size), view.focus.radius)
+            }
+            Projection::ExpMap => {
+                let world = exp_map_to_world(view, px_size, point);
+                let center = view.focus.center;
+                let radius = (world.x - center.x).hypot(world.y - center.y);
+                (world, radius * 2. * PI / px_size.width, radius)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Data, Lens)]
+pub struct IterPolicy {
+    /// Grow the iteration count with the zoom depth
+    pub auto: bool,
+    /// Iteration count, divided by sqrt(2 radius) if `auto`
+    pub count: usize,
+}
+
+impl IterPolicy {
+    pub fn new() -> Self {
+        IterPolicy {
+            auto: true,
+            count: 223,
+        }
+    }
+
+    pub fn max_iter(&self, radius: f64) -> usize {
+        if self.auto {
+            // TODO: tune
+            let f = (0.001 + 2.0 * radius).sqrt();
+            (self.count as f64 / f).floor() as usize
+        } else {
+            self.count
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Data, Lens)]
+pub struct RenderParams {
+    pub formula: Formula,
+    pub julia_c: Point,
+    pub iterations: IterPolicy,
+    /// Escape radius, larger values smooth the coloring
+    pub escape_radius: f64,
+    pub palette: Palette,
+    pub color_mode: ColorMode,
+    pub interior: InteriorMode,
+    pub trap: OrbitTrap,
+    /// Stripe count of the stripe average
+    pub stripe_density: f64,
+    /// Light the exterior as an embossed surface
+    pub shading: bool,
+    /// Light azimuth, in degrees
+    pub light_angle: f64,
+    /// Light elevation above the image plane, in degrees
+    pub light_elevation: f64,
+    /// Surface flatness, higher values soften the relief
+    pub height: f64,
+    pub projection: Projection,
+}
+
+impl RenderParams {
+    pub fn new() -> Self {
+        RenderParams {
+            formula: Formula::Mandelbrot,
+            julia_c: Point::ZERO,
+            iterations: IterPolicy::new(),
+            escape_radius: 10.,
+            palette: Palette::new(),
+            color_mode: ColorMode::Smooth,
+            interior: InteriorMode::Black,
+            trap: OrbitTrap::new(),
+            stripe_density: 5.,
+            shading: false,
+            light_angle: 45.,
+            light_elevation: 45.,
+            height: 1.5,
+            projection: Projection::Linear,
+        }
+    }
+
+    /// Whether both parameters iterate the same orbits and gather the same
+    /// statistics, so that switching only needs recoloring
+    pub fn same_orbits(&self, other: &Self) -> bool {
+        let stripe = self.color_mode == ColorMode::Stripe;
+        self.formula == other.formula
+            && self.julia_c == other.julia_c
+            && self.iterations == other.iterations
+            && self.escape_radius == other.escape_radius
+            && self.projection == other.projection
+            && self.active_trap() == other.active_trap()
+            && (self.color_mode == ColorMode::TriangleInequality)
+                == (other.color_mode == ColorMode::TriangleInequality)
+            && stripe == (other.color_mode == ColorMode::Stripe)
+            && (!stripe || self.stripe_density == other.stripe_density)
+    }
+
+    /// Orbit trap to track while iterating, if the coloring needs it
+    pub fn active_trap(&self) -> Option<&OrbitTrap> {
+        if self.color_mode.uses_trap() {
+            Some(&self.trap)
+        } else {
+            None
+        }
+    }
+}
+
+// Iterations past the escape, reducing the smooth coloring error
+const EXTRA_ITER: usize = 5;
+// Line width of the line art mode, in pixels
+const LINE_WIDTH: f64 = 1.;
+// Spreads integer values over the palette
+const GOLDEN_RATIO: f64 = 0.618033988749895;
+// Shading coefficients
+const AMBIENT: f64 = 0.25;
+const SPECULAR: f64 = 0.5;
+const SHININESS: i32 = 20;
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let n = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    [v[0] / n, v[1] / n, v[2] / n]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn shade(base: RGB, res: &ColorData, params: &RenderParams) -> RGB {
+    // Lambertian diffuse and Blinn specular terms
+    let (az, el) = (
+        params.light_angle.to_radians(),
+        params.light_elevation.to_radians(),
     );
-    let b = res.b;
-    res.b = res.r;
-    res.r = b;
-    res
-}
-
-pub fn px_to_world(focus: &Circle, px_size: &Size, point: &IPoint) -> FPoint {
-    //
-    let mut xr = point.x as f64 / px_size.width;
-    let mut yr = point.y as f64 / px_size.height;
-    // Now transform back to window
-    xr = (xr * 2. - 1.) * focus.radius;
-    yr = (yr * 2. - 1.) * focus.radius;
-    if px_size.width > px_size.height {
-        xr *= px_size.width / px_size.height;
+    let light = [az.cos() * el.cos(), az.sin() * el.cos(), el.sin()];
+    let normal = normalize([res.normal[0] as f64, res.normal[1] as f64, params.height]);
+    let half = normalize([light[0], light[1], light[2] + 1.]);
+    let diffuse = dot(&normal, &light).max(0.);
+    let specular = dot(&normal, &half).max(0.).powi(SHININESS);
+    RGB::BLACK
+        .interpolate(&base, AMBIENT + (1. - AMBIENT) * diffuse)
+        .interpolate(&RGB::WHITE, SPECULAR * specular)
+}
+
+// Smooth iteration count
+fn smooth_iter(res: &ColorData) -> f64 {
+    let l = 1. / 2_f64.log2();
+    5. + res.iter as f64 - (0.5_f64.log2() - res.norm_sqr.log2().log2()) * l
+}
+
+fn smooth_color(res: &ColorData, palette: &Palette) -> RGB {
+    palette.sample(smooth_iter(res) / res.max_iter as f64)
+}
+
+fn average_color(res: &ColorData, palette: &Palette) -> RGB {
+    // Blend the last two averages with the fractional iteration count
+    let (last, prev) = (res.stats[0] as f64, res.stats[1] as f64);
+    let f = smooth_iter(res).rem_euclid(1.);
+    palette.sample(last * f + prev * (1. - f))
+}
+
+fn trap_color(res: &ColorData, trap: &OrbitTrap, mode: ColorMode, palette: &Palette) -> RGB {
+    let [trap_min, trap_avg, pos_r, pos_i] = res.stats.map(|v| v as f64);
+    match mode {
+        ColorMode::TrapMin => palette.sample(trap_min / (trap_min + trap.radius)),
+        ColorMode::TrapAverage => palette.sample(trap_avg / (trap_avg + trap.radius)),
+        _ => {
+            let p = Complex {
+                r: pos_r - trap.center.x,
+                i: pos_i - trap.center.y,
+            };
+            match &trap.texture {
+                Some(texture) if trap.shape == TrapShape::Texture && trap_min == 0. => {
+                    let (u, v) = trap.uv(&p);
+                    texture.sample(u, v)
+                }
+                _ => palette.sample((p.i.atan2(p.r) + PI) / (2. * PI)),
+            }
+        }
+    }
+}
+
+fn interior_color(res: &ColorData, mode: InteriorMode, palette: &Palette) -> RGB {
+    match mode {
+        InteriorMode::Black => RGB::BLACK,
+        InteriorMode::Norm => palette.sample(0.5 * res.norm_sqr.sqrt()),
+        InteriorMode::Period if res.period > 0 => {
+            palette.sample((res.period as f64 * GOLDEN_RATIO).fract())
+        }
+        InteriorMode::Distance if res.distance > 0. => {
+            let shade = (4. * res.distance as f64).powf(0.2).min(1.);
+            RGB::WHITE.interpolate(&RGB::BLACK, shade)
+        }
+        InteriorMode::AtomDomain => palette.sample((res.atom_period as f64 * GOLDEN_RATIO).fract()),
+        _ => RGB::BLACK,
+    }
+}
+
+pub fn color_scheme(res: &ColorData, params: &RenderParams) -> RGB {
+    if !res.escaped {
+        return match params.color_mode {
+            ColorMode::LineArt => RGB::WHITE,
+            _ => interior_color(res, params.interior, &params.palette),
+        };
+    }
+    // Distance to the boundary, in pixels
+    let d = res.distance as f64;
+    let base = match params.color_mode {
+        ColorMode::Smooth => smooth_color(res, &params.palette),
+        ColorMode::Distance => {
+            let shade = (4. * d).powf(0.2).min(1.);
+            RGB::BLACK.interpolate(&smooth_color(res, &params.palette), shade)
+        }
+        ColorMode::LineArt => {
+            let coverage = 1. - (d / LINE_WIDTH).clamp(0., 1.);
+            RGB::WHITE.interpolate(&RGB::BLACK, coverage)
+        }
+        ColorMode::TriangleInequality | ColorMode::Stripe => average_color(res, &params.palette),
+        mode => trap_color(res, &params.trap, mode, &params.palette),
+    };
+    if params.shading {
+        shade(base, res, params)
     } else {
-        yr *= px_size.height / px_size.width;
+        base
     }
+}
+
+/// Exponential map of the focus disk: columns sweep a full turn around
+/// the center, and each row moves inwards by a factor exp(2 pi / width), so
+/// that pixels stay square. A zoom into the center becomes a vertical scroll.
+/// The circles around the center are mapped through the view transform.
+pub fn exp_map_to_world(view: &Viewport, px_size: &Size, point: &IPoint) -> FPoint {
+    let step = 2. * PI / px_size.width;
+    let angle = point.x as f64 * step;
+    let focus = &view.focus;
+    let radius = focus.radius * (-(point.y as f64) * step).exp();
+    let (x, y) = view
+        .transform()
+        .apply(radius * angle.cos(), radius * angle.sin());
     FPoint {
-        x: xr + focus.center.x,
-        y: yr + focus.center.y,
+        x: focus.center.x + x,
+        y: focus.center.y + y,
     }
 }
 
-pub fn mandelbrot(c: Complex, escape_radius_sqr: f64, max_iter: usize) -> ConvResult {
-    let mut z = Complex::zero();
-    let mut z_sqr = Complex::zero();
+// Interior distance estimate from a point of the attracting cycle
+fn interior_distance(c: Complex, z: Complex, period: usize) -> f64 {
+    // Refine the cycle point, solving f^p(z0) = z0 with Newton's method
+    let mut z0 = z;
+    for _ in 0..8 {
+        let mut w = z0;
+        let mut dw = Complex::one();
+        for _ in 0..period {
+            dw = (w * dw).scale(2.);
+            w = w * w + c;
+        }
+        z0 = z0 - (w - z0) / (dw - Complex::one());
+    }
+    // Derivatives of f^p along the cycle
+    let mut z = z0;
+    let mut dz = Complex::one();
+    let mut dc = Complex::zero();
+    let mut dzdz = Complex::zero();
+    let mut dcdz = Complex::zero();
+    for _ in 0..period {
+        dcdz = (z * dcdz + dc * dz).scale(2.);
+        dzdz = (dz * dz + z * dzdz).scale(2.);
+        dc = (z * dc).scale(2.) + Complex::one();
+        dz = (z * dz).scale(2.);
+        z = z * z + c;
+    }
+    let dz_norm_sqr = dz.norm_sqr();
+    if dz_norm_sqr >= 1. {
+        // Not an attracting cycle
+        return 0.;
+    }
+    let denom = dcdz + dzdz * dc / (Complex::one() - dz);
+    (1. - dz_norm_sqr) / denom.norm_sqr().sqrt()
+}
+
+// Running average over the orbit
+struct Average {
+    sum: f64,
+    prev_sum: f64,
+    count: usize,
+}
+
+impl Average {
+    fn new() -> Self {
+        Average {
+            sum: 0.,
+            prev_sum: 0.,
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, t: f64) {
+        self.prev_sum = self.sum;
+        self.sum += t;
+        self.count += 1;
+    }
+
+    // Averages with and without the last term
+    fn result(&self) -> (f64, f64) {
+        if self.count < 2 {
+            return (self.sum, self.sum);
+        }
+        (
+            self.sum / self.count as f64,
+            self.prev_sum / (self.count - 1) as f64,
+        )
+    }
+}
+
+/// Orbit z0, z1, z2, ... of a point, up to the escape or `max_iter` points
+pub fn orbit(
+    point: Complex,
+    escape_radius_sqr: f64,
+    max_iter: usize,
+    params: &RenderParams,
+) -> Vec<Complex> {
+    let (mut z, c) = params.formula.start(point, &params.julia_c);
+    let mut points = vec![z];
+    while points.len() < max_iter && z.norm_sqr() <= escape_radius_sqr {
+        z = z * z + c;
+        points.push(z);
+    }
+    points
+}
+
+// Grid side of the stretch estimate
+const STRETCH_SAMPLES: usize = 17;
+// Largest stretch the estimate undoes
+const MAX_STRETCH: f64 = 8.;
+
+/// Stretch of the structures visible around `center`, as a symmetric matrix
+/// of determinant 1, estimated from the smooth iteration count on a grid of
+/// half size `radius`. The derivative at the center can't be used, dz/dc is
+/// conformal and has no stretch. The mean outer product of the unit gradients
+/// has the stretch factor as eigenvalue ratio, the structures extending along
+/// the eigenvector of the smaller eigenvalue. Identity if too few points
+/// escape. Iterates the whole grid, too slow for the UI thread when deep.
+pub fn feature_stretch(center: Point, radius: f64, max_iter: usize, params: &RenderParams) -> Mat2 {
+    let n = STRETCH_SAMPLES;
+    let escape_radius_sqr = params.escape_radius * params.escape_radius;
+    let values: Vec<Option<f64>> = (0..n * n)
+        .map(|idx| {
+            let (x, y) = (idx % n, idx / n);
+            let offset = |k: usize| radius * (2. * k as f64 / (n - 1) as f64 - 1.);
+            let point = Complex {
+                r: center.x + offset(x),
+                i: center.y + offset(y),
+            };
+            let res = mandelbrot(point, escape_radius_sqr, max_iter, params);
+            // Smooth iteration count, up to a constant
+            Some(res.iter as f64 - res.norm_sqr.ln().log2()).filter(|_| res.escaped)
+        })
+        .collect();
+    let value = |x: usize, y: usize| values[x + n * y];
+
+    // Orientation tensor [[p, q], [q, r]] of the central differences
+    let (mut p, mut q, mut r, mut count) = (0., 0., 0., 0);
+    for y in 1..n - 1 {
+        for x in 1..n - 1 {
+            let gradient = (
+                value(x + 1, y).zip(value(x - 1, y)),
+                value(x, y + 1).zip(value(x, y - 1)),
+            );
+            if let (Some((x1, x0)), Some((y1, y0))) = gradient {
+                let (gx, gy) = (x1 - x0, y1 - y0);
+                let norm = gx.hypot(gy);
+                if norm > 0. && norm.is_finite() {
+                    let (gx, gy) = (gx / norm, gy / norm);
+                    p += gx * gx;
+                    q += gx * gy;
+                    r += gy * gy;
+                    count += 1;
+                }
+            }
+        }
+    }
+    if count < n {
+        return Mat2::IDENTITY;
+    }
+    // Eigenvalues, and the eigenvector of the larger one
+    let (mean, half_diff) = ((p + r) / 2., (p - r) / 2.);
+    let spread = half_diff.hypot(q);
+    let (large, small) = (mean + spread, mean - spread);
+    let (vx, vy) = if q != 0. {
+        (q, large - p)
+    } else if p >= r {
+        (1., 0.)
+    } else {
+        (0., 1.)
+    };
+    let norm = vx.hypot(vy);
+    let (vx, vy) = (vx / norm, vy / norm);
+    let stretch = if small > 0. {
+        (large / small).min(MAX_STRETCH)
+    } else {
+        MAX_STRETCH
+    };
+    // sqrt(k) along the structures, 1 / sqrt(k) across them
+    let (along, across) = (stretch.sqrt(), 1. / stretch.sqrt());
+    Mat2 {
+        a: along * vy * vy + across * vx * vx,
+        b: (across - along) * vx * vy,
+        c: (across - along) * vx * vy,
+        d: along * vx * vx + across * vy * vy,
+    }
+}
+
+pub fn mandelbrot(
+    point: Complex,
+    escape_radius_sqr: f64,
+    max_iter: usize,
+    params: &RenderParams,
+) -> ConvResult {
+    let trap = params.active_trap();
+    let tia = params.color_mode == ColorMode::TriangleInequality;
+    let stripe = params.color_mode == ColorMode::Stripe;
+    let (mut z, c) = params.formula.start(point, &params.julia_c);
+    let mut z_sqr = Complex {
+        r: z.r * z.r,
+        i: z.i * z.i,
+    };
+    // Derivative dz/dc, or dz/dz0 for Julia sets
+    let (mut dz, dc) = match params.formula {
+        Formula::Mandelbrot => (Complex::zero(), 1.),
+        Formula::Julia => (Complex::one(), 0.),
+    };
+    // Orbit trap
+    let mut trap_min = f64::MAX;
+    let mut trap_sum = 0.;
+    let mut trap_pos = Complex::zero();
+    // Periodicity detection, comparing against z at the last power of two
+    let mut z_check = Complex::zero();
+    let mut check_iter = 0;
+    let mut period = 0;
+    // Atom domain
+    let mut min_norm_sqr = f64::MAX;
+    let mut atom_period = 0;
+    // Orbit averages
+    let c_norm = c.norm_sqr().sqrt();
+    let mut tia_avg = Average::new();
+    let mut stripe_avg = Average::new();
+    let mut escaped = false;
     let mut iter = 0;
     let mut terminate_iter = 0;
-    const EXTRA_ITER: usize = 5;
+    const PERIOD_EPS_SQR: f64 = 1e-20;
 
     loop {
+        let prev_norm_sqr = z_sqr.r + z_sqr.i;
+
+        // dz <- 2 z dz + dc
+        let dz_r = 2. * (z.r * dz.r - z.i * dz.i) + dc;
+        dz.i = 2. * (z.r * dz.i + z.i * dz.r);
+        dz.r = dz_r;
+
         z.i = 2. * z.r * z.i + c.i;
         z.r = z_sqr.r - z_sqr.i + c.r;
         z_sqr.r = z.r * z.r;
         z_sqr.i = z.i * z.i;
 
         if terminate_iter == 0 {
+            let norm_sqr = z_sqr.r + z_sqr.i;
+            if tia && iter > 0 {
+                // Position of |z| between the triangle inequality bounds
+                let m = (prev_norm_sqr - c_norm).abs();
+                let big_m = prev_norm_sqr + c_norm;
+                if big_m > m {
+                    tia_avg.push((norm_sqr.sqrt() - m) / (big_m - m));
+                }
+            }
+            if stripe {
+                let t = 0.5 * (params.stripe_density * z.i.atan2(z.r)).sin() + 0.5;
+                stripe_avg.push(t);
+            }
+            if let Some(trap) = trap {
+                let d = trap.distance(&z);
+                trap_sum += d;
+                if d < trap_min {
+                    trap_min = d;
+                    trap_pos = z;
+                }
+            }
             iter += 1;
-            if iter >= max_iter || z_sqr.r + z_sqr.i > escape_radius_sqr {
+            if norm_sqr < min_norm_sqr {
+                min_norm_sqr = norm_sqr;
+                atom_period = iter;
+            }
+            if norm_sqr > escape_radius_sqr {
+                escaped = true;
+                terminate_iter = 1;
+            } else if iter >= max_iter {
                 terminate_iter = 1;
+            } else if (z - z_check).norm_sqr() < PERIOD_EPS_SQR {
+                period = iter - check_iter;
+                break;
+            } else if iter & (iter - 1) == 0 {
+                z_check = z;
+                check_iter = iter;
             }
         } else {
             terminate_iter += 1;
@@ -80,9 +974,45 @@ pub fn mandelbrot(c: Complex, escape_radius_sqr: f64, max_iter: usize) -> ConvRe
         }
     }
 
+    let norm_sqr = z_sqr.i + z_sqr.r;
+    let (distance, normal) = if escaped {
+        // |z| ln|z| / |dz|
+        let distance = 0.5 * norm_sqr.ln() * (norm_sqr / dz.norm_sqr()).sqrt();
+        // Direction of z / dz
+        let u = Complex {
+            r: z.r * dz.r + z.i * dz.i,
+            i: z.i * dz.r - z.r * dz.i,
+        };
+        let u_norm = u.norm_sqr().sqrt();
+        let normal = Complex {
+            r: u.r / u_norm,
+            i: u.i / u_norm,
+        };
+        (distance, normal)
+    } else {
+        (0., Complex::zero())
+    };
+    // The Newton refinement solves for c, only meaningful in the parameter plane
+    let interior_distance = if period > 0 && params.formula == Formula::Mandelbrot {
+        interior_distance(c, z, period)
+    } else {
+        0.
+    };
+
     ConvResult {
         max_iter: max_iter,
         iter: iter,
-        norm_sqr: z_sqr.i + z_sqr.r,
+        escaped: escaped,
+        norm_sqr: norm_sqr,
+        distance: distance,
+        normal: normal,
+        trap_min: trap_min,
+        trap_avg: trap_sum / iter as f64,
+        trap_pos: trap_pos,
+        period: period,
+        atom_period: atom_period,
+        interior_distance: interior_distance,
+        tia: tia_avg.result(),
+        stripe: stripe_avg.result(),
     }
 }
@@ -1,109 +1,598 @@
+use crate::bookmarks::{self, Bookmark};
 use crate::fractal::*;
 use crate::image_utils::{IPoint, RGB};
-use crate::renderer::Renderer;
-use druid::kurbo::{Circle, Rect};
+use crate::keymap::{Action, Keymap};
+use crate::location::Location;
+use crate::renderer::{RenderStats, Renderer};
+use crate::time::{Duration, Instant};
+use crate::viewport::{Mat2, Viewport};
+use druid::kurbo::{BezPath, Circle, Rect};
 use druid::piet::{ImageFormat, InterpolationMode};
 use druid::platform_menus::mac::file::print;
 use druid::widget::prelude::*;
-use druid::{Code, Color, Key, Lens, MouseButton, Point};
+use druid::{Code, Color, Key, Lens, MouseButton, Point, TimerToken};
+use std::sync::Arc;
 
 const MAX_RADIUS: f64 = 2.;
+// Longest orbit path drawn
+const MAX_ORBIT_POINTS: usize = 1000;
+// Zoom octaves per pixel of wheel scrolling
+const WHEEL_ZOOM: f64 = 1. / 400.;
+// Zoom factor of the zoom keys
+const KEY_ZOOM: f64 = std::f64::consts::SQRT_2;
+// Pan distance of the arrow keys, relative to the radius
+const PAN_STEP: f64 = 0.25;
+// Length of the animated transition to a new view
+const TRANSITION_DURATION: Duration = Duration::from_millis(300);
+// Idle time after which a wheel or key gesture is recorded in the history
+const GESTURE_HISTORY_DELAY: Duration = Duration::from_millis(300);
+
+/// View in the navigation history, with the formula it shows
+#[derive(Clone, Copy, PartialEq, Data)]
+pub struct HistoryEntry {
+    pub view: Viewport,
+    pub formula: Formula,
+    pub julia_c: Point,
+}
 
 #[derive(Clone, Data, Lens)]
 pub struct FractalData {
-    focus: Circle,
+    #[lens(name = "view_lens")]
+    view: Viewport,
     selection: Rect,
     progress: f64,
+    pub params: RenderParams,
+    // Navigation history, and index of the current view
+    #[lens(ignore)]
+    history: Arc<Vec<HistoryEntry>>,
+    history_idx: usize,
+    pub bookmarks: Arc<Vec<Bookmark>>,
+    // Name of the next bookmark
+    bookmark_name: String,
+    pub show_bookmarks: bool,
+    pub show_inspector: bool,
+    // Point under the cursor, if the inspector is shown
+    pub inspection: Option<Inspection>,
+    pub show_stats: bool,
+    // Render statistics, if the overlay is shown
+    pub stats: RenderStats,
+    pub orbit_display: OrbitDisplay,
+    pub show_julia: bool,
+    pub show_overview: bool,
+    pub show_params: bool,
+    /// Shift the palette offset continuously
+    pub cycle_palette: bool,
+    /// Palette cycles per second, negative values cycle backwards
+    pub cycle_speed: f64,
+    // World point under the cursor, tracked for the Julia preview
+    pub cursor: Option<Point>,
+    /// Size of the main view, in pixels
+    pub view_size: Size,
+}
+
+/// Orbit overlay of the point under the cursor
+#[derive(Clone, Copy, PartialEq, Data)]
+pub enum OrbitDisplay {
+    Hidden,
+    Path,
+    /// Path with the escape circle and the periodic cycle
+    Markers,
+}
+
+impl OrbitDisplay {
+    pub fn next(&self) -> Self {
+        match self {
+            OrbitDisplay::Hidden => OrbitDisplay::Path,
+            OrbitDisplay::Path => OrbitDisplay::Markers,
+            OrbitDisplay::Markers => OrbitDisplay::Hidden,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OrbitDisplay::Hidden => "off",
+            OrbitDisplay::Path => "path",
+            OrbitDisplay::Markers => "markers",
+        }
+    }
+}
+
+/// Iteration details of a single point
+#[derive(Clone, Data)]
+pub struct Inspection {
+    pub re: f64,
+    pub im: f64,
+    pub iter: usize,
+    pub max_iter: usize,
+    pub norm: f64,
+    pub escaped: bool,
+    pub period: usize,
+}
+
+impl Inspection {
+    pub fn new(c: Point, params: &RenderParams, radius: f64) -> Self {
+        let max_iter = params.iterations.max_iter(radius);
+        let escape_radius_sqr = params.escape_radius * params.escape_radius;
+        let res = mandelbrot(
+            Complex { r: c.x, i: c.y },
+            escape_radius_sqr,
+            max_iter,
+            params,
+        );
+        Inspection {
+            re: c.x,
+            im: c.y,
+            iter: res.iter(),
+            max_iter: max_iter,
+            norm: res.norm(),
+            escaped: res.escaped(),
+            period: res.period(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let status = if self.escaped {
+            format!("escaped at {}", self.iter)
+        } else if self.period > 0 {
+            format!("period {}", self.period)
+        } else {
+            format!("undecided after {}", self.max_iter)
+        };
+        // Floats are printed with the shortest representation that round-trips
+        format!(
+            "re {}\nim {}\niterations {}\n|z| {:.6}\n{}",
+            self.re, self.im, self.iter, self.norm, status
+        )
+    }
+}
+
+pub fn zoom_factor_str(radius: f64) -> String {
+    format!("{}x", (MAX_RADIUS / radius).round())
 }
 
 impl FractalData {
     pub fn new() -> Self {
         let mut instance = FractalData {
-            focus: Circle::new(Point::ZERO, 0.),
+            view: Viewport::new(Circle::new(Point::ZERO, 0.)),
             selection: Rect::ZERO,
             progress: 0.,
+            params: RenderParams::new(),
+            history: Arc::new(Vec::new()),
+            history_idx: 0,
+            bookmarks: Arc::new(bookmarks::load()),
+            bookmark_name: String::new(),
+            show_bookmarks: false,
+            show_inspector: false,
+            inspection: None,
+            show_stats: false,
+            stats: RenderStats::default(),
+            orbit_display: OrbitDisplay::Hidden,
+            show_julia: false,
+            show_overview: false,
+            show_params: false,
+            cycle_palette: false,
+            cycle_speed: 0.1,
+            cursor: None,
+            view_size: Size::ZERO,
         };
         instance.zoom_reset();
         instance
     }
 
+    /// Center of the full view of the current formula
+    fn home_center(&self) -> Point {
+        match self.params.formula {
+            Formula::Mandelbrot => Point::new(-0.5, 0.),
+            Formula::Julia => Point::ZERO,
+        }
+    }
+
+    /// Full view of the current formula
+    pub fn home_focus(&self) -> Circle {
+        Circle::new(self.home_center(), MAX_RADIUS)
+    }
+
+    pub fn focus(&self) -> Circle {
+        self.view.focus
+    }
+
+    pub fn view(&self) -> Viewport {
+        self.view
+    }
+
+    /// Undo the stretch of the structures in `view`, estimated with
+    /// `feature_stretch`, unless the view changed since. The current rotation
+    /// is kept.
+    pub fn finish_unskew(&mut self, view: &Viewport, skew: Mat2) {
+        if self.view == *view {
+            self.set_skew(skew);
+        }
+    }
+
+    /// Zoom depth, in decades below the full view
+    pub fn zoom_depth(&self) -> f64 {
+        (MAX_RADIUS / self.view.focus.radius).log10()
+    }
+
+    /// Move the view center to a world point
+    pub fn pan_to(&mut self, center: Point) {
+        self.view.focus.center = center;
+        self.push_history();
+    }
+
     fn clip_zoom(&mut self) {
-        if self.focus.radius > MAX_RADIUS {
-            self.focus.radius = MAX_RADIUS;
-            self.focus.center.x = -0.5;
-            self.focus.center.y = 0.;
+        if self.view.focus.radius > MAX_RADIUS {
+            self.view.focus = self.home_focus();
         }
     }
 
     pub fn zoom_reset(&mut self) {
-        self.focus = Circle {
-            center: Point::new(-0.5, 0.),
-            radius: MAX_RADIUS,
-        };
+        self.view = Viewport::new(self.home_focus());
         self.selection = Rect::ZERO;
+        self.push_history();
+    }
+
+    pub fn reset_skew(&mut self) {
+        self.set_skew(Mat2::IDENTITY);
+    }
+
+    pub fn set_skew(&mut self, skew: Mat2) {
+        self.view.skew = skew;
+        self.push_history();
     }
 
     pub fn zoom_center(&mut self, factor: f64) {
-        self.focus.radius = self.focus.radius / factor;
+        self.view.focus.radius /= factor;
+        self.clip_zoom();
+        self.push_history();
+    }
+
+    /// Center the view on a screen point and zoom by `factor`
+    pub fn zoom_point(&mut self, size: &Size, pos: Point, factor: f64) {
+        self.view.focus.center = self.view.px_to_world(*size, pos);
+        self.view.focus.radius /= factor;
         self.clip_zoom();
+        self.push_history();
     }
 
-    pub fn zoom_point(&mut self, size: &Size, point: &IPoint, factor: f64) {
-        // Unzoom
-        let p0 = px_to_world(&self.focus, size, point);
-        self.focus.center = Point { x: p0.x, y: p0.y };
-        self.focus.radius = self.focus.radius / factor;
+    /// Zoom by `factor` keeping the world point under `pos` fixed on screen.
+    /// History is left to the caller, so continuous gestures record one entry.
+    pub fn zoom_at(&mut self, size: &Size, pos: Point, factor: f64) {
+        self.view.zoom_at(*size, pos, factor);
         self.clip_zoom();
     }
 
+    /// Move the view by a fraction of its radius, along the screen axes
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        self.view.pan(dx, dy);
+    }
+
     pub fn zoom_rect(&mut self, size: &Size, selection: Rect) {
-        // Zoom
-        let p0 = px_to_world(
-            &self.focus,
-            size,
-            &IPoint {
-                x: selection.x0 as usize,
-                y: selection.y0 as usize,
-            },
-        );
-        let p1 = px_to_world(
-            &self.focus,
-            size,
-            &IPoint {
-                x: selection.x1 as usize,
-                y: selection.y1 as usize,
-            },
-        );
-        self.focus.center = Point {
-            x: (p0.x + p1.x) / 2.,
-            y: (p0.y + p1.y) / 2.,
+        self.view.zoom_rect(*size, selection);
+        self.push_history();
+    }
+
+    /// Record the current view, dropping the views that were undone
+    pub fn push_history(&mut self) {
+        let entry = HistoryEntry {
+            view: self.view,
+            formula: self.params.formula,
+            julia_c: self.params.julia_c,
         };
-        self.focus.radius = (p0.x - p1.x).abs().min((p0.y - p1.y).abs()) / 2.;
+        if self.history.get(self.history_idx) == Some(&entry) {
+            return;
+        }
+        let history = Arc::make_mut(&mut self.history);
+        history.truncate(self.history_idx + 1);
+        history.push(entry);
+        self.history_idx = history.len() - 1;
+    }
+
+    pub fn history_jump(&mut self, idx: usize) {
+        if let Some(entry) = self.history.get(idx) {
+            self.view = entry.view;
+            self.params.formula = entry.formula;
+            self.params.julia_c = entry.julia_c;
+            self.history_idx = idx;
+            self.selection = Rect::ZERO;
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history_idx > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history_idx + 1 < self.history.len()
+    }
+
+    pub fn undo(&mut self) {
+        if self.can_undo() {
+            self.history_jump(self.history_idx - 1);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if self.can_redo() {
+            self.history_jump(self.history_idx + 1);
+        }
+    }
+
+    /// History entries and index of the current one
+    pub fn history(&self) -> (Arc<Vec<HistoryEntry>>, usize) {
+        (self.history.clone(), self.history_idx)
+    }
+
+    pub fn location(&self) -> Location {
+        Location::new(&self.view, &self.params)
+    }
+
+    pub fn set_location(&mut self, location: &Location) {
+        self.view = location.view();
+        self.selection = Rect::ZERO;
+        location.apply(&mut self.params);
+        self.push_history();
+    }
+
+    /// Switch formula, showing its full view
+    pub fn set_formula(&mut self, formula: Formula) {
+        self.params.formula = formula;
+        self.zoom_reset();
+    }
+
+    /// Swap the view into the Julia set of `c`
+    pub fn enter_julia(&mut self, c: Point) {
+        self.params.julia_c = c;
+        self.set_formula(Formula::Julia);
+    }
+
+    /// Bookmark the current view under `bookmark_name`, without a thumbnail.
+    /// Names that would break the bookmarks file are rejected.
+    pub fn add_bookmark(&mut self) -> Option<Location> {
+        let name = match self.bookmark_name.trim() {
+            "" => format!("bookmark {}", self.bookmarks.len() + 1),
+            name => name.to_string(),
+        };
+        if !bookmarks::valid_name(&name) {
+            println!("Invalid bookmark name '{}'", name.escape_default());
+            return None;
+        }
+        let location = self.location();
+        let bookmark = Bookmark::new(name, location.clone());
+        Arc::make_mut(&mut self.bookmarks).push(bookmark);
+        self.bookmark_name.clear();
+        bookmarks::save(&self.bookmarks);
+        Some(location)
+    }
+
+    /// Show a rendered thumbnail on the bookmarks of `location`
+    pub fn set_thumbnail(&mut self, location: &Location, thumbnail: &Arc<Vec<u8>>) {
+        for bookmark in Arc::make_mut(&mut self.bookmarks).iter_mut() {
+            if bookmark.location == *location {
+                bookmark.thumbnail = thumbnail.clone();
+            }
+        }
+    }
+
+    pub fn remove_bookmark(&mut self, bookmark: &Bookmark) {
+        Arc::make_mut(&mut self.bookmarks).retain(|b| !b.same(bookmark));
+        bookmarks::save(&self.bookmarks);
     }
 
     pub fn zoom_factor_str(&self) -> String {
-        format!("{}x", (MAX_RADIUS / self.focus.radius).round())
+        zoom_factor_str(self.view.focus.radius)
     }
 }
 
+/// Snapshot of a rendered image, shown scaled while the renderer catches up
+struct Preview {
+    image_data: Vec<u8>,
+    image_size: IPoint,
+    view: Viewport,
+}
+
+/// Animation from a previous view to the current focus
+struct Transition {
+    from: Viewport,
+    start: Instant,
+}
+
 pub struct FractalWidget {
     size: Size,
     renderer: Renderer,
     image: Vec<RGB>,
     image_data: Vec<u8>,
+    image_size: IPoint,
     progress: f64,
+    stats: RenderStats,
     drag_center: Option<Point>,
+    // Rotation at the start of a rotate drag, and cursor angle around the center
+    drag_rotation: Option<(f64, f64)>,
+    mouse_pos: Point,
+    preview: Option<Preview>,
+    // Orbit of the point under the cursor, and its attracting cycle
+    orbit: Vec<Complex>,
+    cycle: Vec<Complex>,
+    transition: Option<Transition>,
+    // Whether the focus change comes from a continuous gesture, not animated
+    in_gesture: bool,
+    gesture_timer: TimerToken,
+    keymap: Keymap,
 }
 
 impl FractalWidget {
     pub fn new() -> Self {
         FractalWidget {
             size: Size::ZERO,
-            renderer: Renderer::new(),
+            renderer: Renderer::with_recoloring(),
             image: Vec::new(),
             image_data: Vec::new(),
+            image_size: IPoint { x: 0, y: 0 },
             progress: 0.,
+            stats: RenderStats::default(),
             drag_center: None,
+            drag_rotation: None,
+            mouse_pos: Point::ZERO,
+            preview: None,
+            orbit: Vec::new(),
+            cycle: Vec::new(),
+            transition: None,
+            in_gesture: false,
+            gesture_timer: TimerToken::INVALID,
+            keymap: Keymap::load(),
+        }
+    }
+
+    /// Keep the current image, rendered at `view`, as a preview of the next view
+    fn snapshot(&mut self, view: &Viewport) {
+        if self.preview.is_none() && !self.image.is_empty() {
+            let mut image_data = Vec::new();
+            RGB::create_image_data(&self.image, &mut image_data);
+            self.preview = Some(Preview {
+                image_data: image_data,
+                image_size: self.image_size,
+                view: *view,
+            });
+        }
+        // Drop the stale image, the preview is drawn instead
+        self.image.fill(RGB::TRANSPARENT);
+    }
+
+    /// View on screen, on the way to `view` during a transition
+    fn view(&self, view: &Viewport) -> Viewport {
+        let transition = match &self.transition {
+            Some(transition) => transition,
+            None => return *view,
+        };
+        let t = transition.start.elapsed().as_secs_f64() / TRANSITION_DURATION.as_secs_f64();
+        if t >= 1. {
+            return *view;
+        }
+        // Ease in and out
+        let t = t * t * (3. - 2. * t);
+        transition.from.lerp(view, t)
+    }
+
+    /// Move the view continuously, keeping the current image as a preview.
+    /// The view is recorded in the history once the gesture pauses.
+    fn view_gesture(
+        &mut self,
+        ctx: &mut EventCtx,
+        data: &mut FractalData,
+        update: impl FnOnce(&mut FractalData),
+    ) {
+        self.snapshot(&data.view);
+        self.in_gesture = true;
+        self.transition = None;
+        update(data);
+        self.gesture_timer = ctx.request_timer(GESTURE_HISTORY_DELAY);
+        ctx.request_paint();
+    }
+
+    /// Update the inspector with the point under the cursor
+    fn inspect(&self, data: &mut FractalData) {
+        if !data.show_inspector {
+            data.inspection = None;
+            return;
+        }
+        let c = data.view.px_to_world(self.size, self.mouse_pos);
+        data.inspection = Some(Inspection::new(c, &data.params, data.view.focus.radius));
+    }
+
+    /// Trace the orbit of the point under the cursor
+    fn trace_orbit(&mut self, data: &FractalData) {
+        self.orbit.clear();
+        self.cycle.clear();
+        if data.orbit_display == OrbitDisplay::Hidden {
+            return;
+        }
+        let w = data.view.px_to_world(self.size, self.mouse_pos);
+        let c = Complex { r: w.x, i: w.y };
+        let max_iter = data.params.iterations.max_iter(data.view.focus.radius);
+        let escape_radius_sqr = data.params.escape_radius * data.params.escape_radius;
+        let mut orbit = orbit(c, escape_radius_sqr, max_iter, &data.params);
+        // The converged tail of the orbit approximates the cycle
+        let period = mandelbrot(c, escape_radius_sqr, max_iter, &data.params).period();
+        if period > 0 && period <= orbit.len() {
+            self.cycle = orbit[orbit.len() - period..].to_vec();
+        }
+        orbit.truncate(MAX_ORBIT_POINTS);
+        self.orbit = orbit;
+    }
+
+    /// Draw the orbit over the fractal
+    fn paint_orbit(&self, ctx: &mut PaintCtx, data: &FractalData) {
+        let size = ctx.size();
+        let to_screen = |z: &Complex| data.view.world_to_px(size, Point::new(z.r, z.i));
+        let mut path = BezPath::new();
+        for (idx, z) in self.orbit.iter().enumerate() {
+            if idx == 0 {
+                path.move_to(to_screen(z));
+            } else {
+                path.line_to(to_screen(z));
+            }
+        }
+        ctx.stroke(&path, &Color::WHITE.with_alpha(0.8), 1.);
+        if data.orbit_display != OrbitDisplay::Markers {
+            return;
+        }
+        // Iterates
+        for z in self.orbit.iter() {
+            ctx.fill(Circle::new(to_screen(z), 2.), &Color::WHITE);
+        }
+        // Escape circle
+        let center = to_screen(&Complex::zero());
+        let edge = to_screen(&Complex {
+            r: data.params.escape_radius,
+            i: 0.,
+        });
+        let escape_circle = Circle::new(center, (edge.x - center.x).abs());
+        ctx.stroke(escape_circle, &Color::rgb8(255, 200, 0), 1.);
+        // Attracting cycle
+        if !self.cycle.is_empty() {
+            let mut path = BezPath::new();
+            path.move_to(to_screen(&self.cycle[0]));
+            for z in self.cycle.iter().skip(1) {
+                path.line_to(to_screen(z));
+            }
+            path.close_path();
+            let cycle_color = Color::rgb8(0, 220, 255);
+            ctx.stroke(&path, &cycle_color, 2.);
+            for z in self.cycle.iter() {
+                ctx.fill(Circle::new(to_screen(z), 4.), &cycle_color);
+            }
+        }
+    }
+
+    /// Angle of `pos` around the widget center, in degrees
+    fn cursor_angle(&self, pos: Point) -> f64 {
+        let dx = pos.x - self.size.width / 2.;
+        let dy = pos.y - self.size.height / 2.;
+        dy.atan2(dx).to_degrees()
+    }
+
+    fn run_action(&mut self, ctx: &mut EventCtx, data: &mut FractalData, action: Action) {
+        let size = self.size;
+        let center = Point::new(size.width / 2., size.height / 2.);
+        match action {
+            Action::PanLeft => self.view_gesture(ctx, data, |data| data.pan(-PAN_STEP, 0.)),
+            Action::PanRight => self.view_gesture(ctx, data, |data| data.pan(PAN_STEP, 0.)),
+            Action::PanUp => self.view_gesture(ctx, data, |data| data.pan(0., -PAN_STEP)),
+            Action::PanDown => self.view_gesture(ctx, data, |data| data.pan(0., PAN_STEP)),
+            Action::ZoomIn => {
+                self.view_gesture(ctx, data, |data| data.zoom_at(&size, center, KEY_ZOOM))
+            }
+            Action::ZoomOut => {
+                self.view_gesture(ctx, data, |data| data.zoom_at(&size, center, 1. / KEY_ZOOM))
+            }
+            Action::Reset => data.zoom_reset(),
+            Action::Home => {
+                data.params = RenderParams::new();
+                data.zoom_reset();
+            }
+            Action::Undo => data.undo(),
+            Action::Redo => data.redo(),
+            Action::Iterations(policy) => data.params.iterations = policy,
         }
     }
 }
@@ -119,35 +608,72 @@ impl Widget<FractalData> for FractalWidget {
         match event {
             Event::MouseDown(mouse) => {
                 ctx.set_active(true);
+                ctx.request_focus();
+                if mouse.button == MouseButton::Right || mouse.mods.ctrl() {
+                    let angle = self.cursor_angle(mouse.pos);
+                    self.drag_rotation = Some((data.view.rotation, angle));
+                }
                 data.selection.x0 = mouse.pos.x;
                 data.selection.y0 = mouse.pos.y;
                 data.selection.x1 = mouse.pos.x;
                 data.selection.y1 = mouse.pos.y;
                 ctx.request_paint();
             }
+            Event::WindowConnected => {
+                ctx.request_focus();
+            }
             Event::KeyDown(key_event) => {
                 if key_event.code == Code::ShiftLeft || key_event.code == Code::ShiftRight {
-                    self.drag_center = Option::Some(data.focus.center);
+                    self.drag_center = Option::Some(data.view.focus.center);
                 }
+                if let Some(action) = self.keymap.action(key_event) {
+                    self.run_action(ctx, data, action);
+                }
+            }
+            Event::Wheel(mouse) => {
+                let size = self.size;
+                let factor = (-mouse.wheel_delta.y * WHEEL_ZOOM).exp2();
+                self.view_gesture(ctx, data, |data| data.zoom_at(&size, mouse.pos, factor));
+            }
+            Event::Zoom(delta) => {
+                // Trackpad pinch, anchored at the last known cursor position.
+                // exp keeps the factor positive on large deltas, ~1 + delta on small ones
+                let (size, pos) = (self.size, self.mouse_pos);
+                let factor = delta.exp();
+                self.view_gesture(ctx, data, |data| data.zoom_at(&size, pos, factor));
+            }
+            Event::Timer(token) if *token == self.gesture_timer => {
+                data.push_history();
             }
             Event::MouseMove(mouse) => {
+                // Covered by an overlay, see `StackWidget`
+                if !ctx.is_hot() && !ctx.is_active() {
+                    data.inspection = None;
+                    self.orbit.clear();
+                    self.cycle.clear();
+                    ctx.request_paint();
+                    return;
+                }
+                self.mouse_pos = mouse.pos;
+                self.inspect(data);
+                self.trace_orbit(data);
+                if data.show_julia {
+                    data.cursor = Some(data.view.px_to_world(self.size, mouse.pos));
+                }
                 if ctx.is_active() {
-                    if let Some(center) = self.drag_center {
-                        let p0 = IPoint {
-                            x: data.selection.x0 as usize,
-                            y: data.selection.y0 as usize,
-                        };
-                        let p1 = IPoint {
-                            x: data.selection.x1 as usize,
-                            y: data.selection.y1 as usize,
-                        };
-                        let w0 = px_to_world(&data.focus, &ctx.size(), &p0);
-                        let w1 = px_to_world(&data.focus, &ctx.size(), &p1);
-
-                        data.focus.center = Point {
-                            x: center.x - w1.x + w0.x,
-                            y: center.y - w1.y + w0.y,
-                        };
+                    if let Some((start_rotation, start_angle)) = self.drag_rotation {
+                        // Turn the view with the cursor around the center
+                        let turn = self.cursor_angle(mouse.pos) - start_angle;
+                        self.view_gesture(ctx, data, |data| {
+                            data.view.rotation = (start_rotation - turn).rem_euclid(360.);
+                        });
+                    } else if let Some(center) = self.drag_center {
+                        let p0 = Point::new(data.selection.x0, data.selection.y0);
+                        let p1 = Point::new(data.selection.x1, data.selection.y1);
+                        data.view.focus.center = center;
+                        data.view.drag(ctx.size(), p0, p1);
+                        self.in_gesture = true;
+                        self.transition = None;
                     }
                     data.selection.x1 = mouse.pos.x;
                     data.selection.y1 = mouse.pos.y;
@@ -156,7 +682,9 @@ impl Widget<FractalData> for FractalWidget {
             }
             Event::MouseUp(_mouse) => {
                 ctx.set_active(false);
-                if self.drag_center.is_none() {
+                if self.drag_rotation.take().is_some() {
+                    data.push_history();
+                } else if self.drag_center.is_none() {
                     // Update selection
                     if data.selection.x1 < data.selection.x0 {
                         swap(&mut data.selection.x0, &mut data.selection.x1);
@@ -165,24 +693,41 @@ impl Widget<FractalData> for FractalWidget {
                         swap(&mut data.selection.y0, &mut data.selection.y1);
                     }
                     if data.selection.area() < 4. {
-                        let point = IPoint {
-                            x: data.selection.x0 as usize,
-                            y: data.selection.y0 as usize,
-                        };
-                        data.zoom_point(&self.size, &point, 0.5);
+                        let point = Point::new(data.selection.x0, data.selection.y0);
+                        data.zoom_point(&self.size, point, 0.5);
                     } else {
                         data.zoom_rect(&self.size, data.selection);
                     }
+                } else {
+                    data.push_history();
                 }
                 // Cancel drag
                 self.drag_center = None;
-                // Clear image
-                self.image.fill(RGB::TRANSPARENT);
                 ctx.request_paint();
             }
-            Event::AnimFrame(_interval) => {
+            Event::AnimFrame(interval) => {
                 // Populate progress
                 data.progress = self.progress;
+                data.view_size = self.size;
+                if data.show_stats {
+                    data.stats = self.stats.clone();
+                }
+                // Cycle colors, recolored from the stored iteration results
+                if data.cycle_palette {
+                    let shift = data.cycle_speed * *interval as f64 * 1e-9;
+                    let palette = &mut data.params.palette;
+                    palette.offset = (palette.offset + shift).rem_euclid(1.);
+                }
+                // Hide the inspector once the cursor leaves
+                if !ctx.is_hot() && data.inspection.is_some() {
+                    data.inspection = None;
+                }
+                // End transition
+                if let Some(transition) = &self.transition {
+                    if transition.start.elapsed() >= TRANSITION_DURATION {
+                        self.transition = None;
+                    }
+                }
                 // Anim frame requested
                 ctx.request_anim_frame();
                 ctx.request_paint();
@@ -211,11 +756,32 @@ impl Widget<FractalData> for FractalWidget {
 
     fn update(
         &mut self,
-        _ctx: &mut UpdateCtx,
-        _old_data: &FractalData,
-        _data: &FractalData,
+        ctx: &mut UpdateCtx,
+        old_data: &FractalData,
+        data: &FractalData,
         _env: &Env,
     ) {
+        let in_gesture = self.in_gesture;
+        self.in_gesture = false;
+        if old_data.view == data.view {
+            if !old_data.params.same_orbits(&data.params) {
+                // Show the previous image until the new parameters are rendered
+                self.snapshot(&data.view);
+                ctx.request_paint();
+            }
+            return;
+        }
+        if in_gesture {
+            return;
+        }
+        // Animate from the view currently on screen
+        let from = self.view(&old_data.view);
+        self.snapshot(&old_data.view);
+        self.transition = Some(Transition {
+            from: from,
+            start: Instant::now(),
+        });
+        ctx.request_paint();
     }
 
     fn layout(
@@ -231,9 +797,35 @@ impl Widget<FractalData> for FractalWidget {
 
     fn paint(&mut self, ctx: &mut PaintCtx, data: &FractalData, _env: &Env) {
         // Render fractal
-        self.renderer.resize(ctx.size(), data.focus);
+        self.renderer.resize(ctx.size(), data.view, &data.params);
         let result = self.renderer.update(&mut self.image);
         self.progress = result.progress;
+        if data.show_stats {
+            self.stats = self.renderer.stats();
+        }
+        self.image_size = result.image_size;
+        if (result.stage > 0 || result.complete) && self.transition.is_none() {
+            self.preview = None;
+        }
+        let view = self.view(&data.view);
+        let image_rect = Rect::from_origin_size(Point::ORIGIN, ctx.size());
+
+        // Draw preview
+        if let Some(preview) = &self.preview {
+            let image = ctx
+                .make_image(
+                    preview.image_size.x,
+                    preview.image_size.y,
+                    &preview.image_data,
+                    ImageFormat::RgbaSeparate,
+                )
+                .unwrap();
+            let transform = preview.view.image_transform(ctx.size(), &view);
+            ctx.with_save(|ctx| {
+                ctx.transform(transform);
+                ctx.draw_image(&image, image_rect, InterpolationMode::Bilinear);
+            });
+        }
 
         if !self.image.is_empty() {
             RGB::create_image_data(&self.image, &mut self.image_data);
@@ -245,16 +837,20 @@ impl Widget<FractalData> for FractalWidget {
                     ImageFormat::RgbaSeparate,
                 )
                 .unwrap();
-            let size = ctx.size();
-            ctx.draw_image(
-                &image,
-                Rect::from_origin_size(Point::ORIGIN, size),
-                InterpolationMode::Bilinear,
-            );
+            let transform = data.view.image_transform(ctx.size(), &view);
+            ctx.with_save(|ctx| {
+                ctx.transform(transform);
+                ctx.draw_image(&image, image_rect, InterpolationMode::Bilinear);
+            });
+        }
+
+        // Draw orbit
+        if ctx.is_hot() && data.orbit_display != OrbitDisplay::Hidden {
+            self.paint_orbit(ctx, data);
         }
 
         // Draw selection
-        if ctx.is_active() && self.drag_center.is_none() {
+        if ctx.is_active() && self.drag_center.is_none() && self.drag_rotation.is_none() {
             let stroke_color = Color::WHITE;
             let fill_color = Color::BLACK.with_alpha(0.2);
             ctx.fill(data.selection, &fill_color);
@@ -40,7 +40,7 @@ impl From<IPoint> for FPoint {
     }
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct RGB {
     pub r: u8,
     pub g: u8,
@@ -55,6 +55,12 @@ impl RGB {
         b: 0,
         a: 255,
     };
+    pub const WHITE: Self = RGB {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
     pub const TRANSPARENT: Self = RGB {
         r: 0,
         g: 0,
@@ -74,6 +80,24 @@ impl RGB {
         }
     }
 
+    /// `rrggbb` hex code, ignoring alpha
+    pub fn to_hex(&self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |k: usize| u8::from_str_radix(&hex[2 * k..2 * k + 2], 16).ok();
+        Some(RGB {
+            r: channel(0)?,
+            g: channel(1)?,
+            b: channel(2)?,
+            a: 255,
+        })
+    }
+
     pub fn interpolate(&self, other: &RGB, alpha: f64) -> Self {
         let a = alpha.clamp(0., 1.);
         RGB {
@@ -84,6 +108,26 @@ impl RGB {
         }
     }
 
+    /// Hue in degrees, saturation and value in [0, 1]
+    pub fn to_hsv(&self) -> (f64, f64, f64) {
+        let r = self.r as f64 / 255.;
+        let g = self.g as f64 / 255.;
+        let b = self.b as f64 / 255.;
+        let max = r.max(g).max(b);
+        let d = max - r.min(g).min(b);
+        let h = if d == 0. {
+            0.
+        } else if max == r {
+            60. * ((g - b) / d).rem_euclid(6.)
+        } else if max == g {
+            60. * ((b - r) / d + 2.)
+        } else {
+            60. * ((r - g) / d + 4.)
+        };
+        let s = if max == 0. { 0. } else { d / max };
+        (h, s, max)
+    }
+
     pub fn from_hsv(h: f64, s: f64, v: f64) -> Self {
         let v = v.clamp(0., 1.);
         let hp = h / 60.0;
@@ -161,3 +205,80 @@ impl RGB {
         }
     }
 }
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    pub size: IPoint,
+    pub pixels: Vec<RGB>,
+}
+
+impl Texture {
+    /// Parse a binary (P6) ppm image with a max value of 255
+    pub fn from_ppm(bytes: &[u8]) -> Option<Self> {
+        // Header tokens: magic, width, height, max value
+        let mut tokens: Vec<String> = Vec::new();
+        let mut idx = 0;
+        while tokens.len() < 4 {
+            let c = *bytes.get(idx)?;
+            if c == b'#' {
+                while *bytes.get(idx)? != b'\n' {
+                    idx += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                idx += 1;
+            } else {
+                let start = idx;
+                while idx < bytes.len() && !bytes[idx].is_ascii_whitespace() {
+                    idx += 1;
+                }
+                tokens.push(String::from_utf8_lossy(&bytes[start..idx]).to_string());
+            }
+        }
+        // Single whitespace before the raster
+        idx += 1;
+        if tokens[0] != "P6" || tokens[3] != "255" {
+            return None;
+        }
+        let w: usize = tokens[1].parse().ok()?;
+        let h: usize = tokens[2].parse().ok()?;
+        if w == 0 || h == 0 {
+            return None;
+        }
+        // Reject dimensions larger than the file before allocating
+        let len = w.checked_mul(h)?.checked_mul(3)?;
+        let raster = bytes.get(idx..idx.checked_add(len)?)?;
+        let pixels = raster
+            .chunks(3)
+            .map(|p| RGB {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+                a: 255,
+            })
+            .collect();
+        Some(Texture {
+            size: IPoint::new(w, h),
+            pixels: pixels,
+        })
+    }
+
+    /// Encode as a binary (P6) ppm image, dropping the alpha channel
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.size.x, self.size.y).into_bytes();
+        bytes.reserve(self.pixels.len() * 3);
+        for p in self.pixels.iter() {
+            bytes.extend_from_slice(&[p.r, p.g, p.b]);
+        }
+        bytes
+    }
+
+    /// Nearest texel at the normalized coordinates (u, v) in [0, 1)
+    pub fn sample(&self, u: f64, v: f64) -> RGB {
+        if self.pixels.is_empty() {
+            return RGB::TRANSPARENT;
+        }
+        let x = ((u * self.size.x as f64) as usize).min(self.size.x - 1);
+        let y = ((v * self.size.y as f64) as usize).min(self.size.y - 1);
+        self.pixels[x + self.size.x * y]
+    }
+}
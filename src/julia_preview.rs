@@ -0,0 +1,134 @@
+use crate::fractal::{Formula, RenderParams};
+use crate::fractal_widget::FractalData;
+use crate::image_utils::RGB;
+use crate::renderer::Renderer;
+use crate::viewport::Viewport;
+use druid::kurbo::{Circle, Rect};
+use druid::piet::{ImageFormat, InterpolationMode};
+use druid::widget::prelude::*;
+use druid::{Color, Point};
+
+// Radius of the disk containing the connected Julia sets
+const JULIA_RADIUS: f64 = 2.;
+// Rendered resolution relative to the inset size, favoring responsiveness
+const RESOLUTION: f64 = 0.5;
+
+/// Inset rendering the Julia set of the point under the cursor
+pub struct JuliaPreview {
+    // Created on the first paint
+    renderer: Option<Renderer>,
+    image: Vec<RGB>,
+    image_data: Vec<u8>,
+    // Last c under the cursor, kept while the cursor is over the inset
+    c: Point,
+}
+
+impl JuliaPreview {
+    pub fn new() -> Self {
+        JuliaPreview {
+            renderer: None,
+            image: Vec::new(),
+            image_data: Vec::new(),
+            c: Point::ZERO,
+        }
+    }
+
+    fn params(&self, data: &FractalData) -> RenderParams {
+        let mut params = data.params.clone();
+        params.formula = Formula::Julia;
+        params.julia_c = self.c;
+        params
+    }
+}
+
+impl Widget<FractalData> for JuliaPreview {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut FractalData, _env: &Env) {
+        match event {
+            Event::MouseDown(_) => {
+                ctx.set_active(true);
+                ctx.set_handled();
+            }
+            Event::MouseUp(_) => {
+                if ctx.is_active() && ctx.is_hot() {
+                    data.enter_julia(self.c);
+                }
+                ctx.set_active(false);
+                ctx.set_handled();
+            }
+            // Keep the main view from zooming under the inset
+            Event::Wheel(_) => ctx.set_handled(),
+            Event::AnimFrame(_interval) => {
+                ctx.request_anim_frame();
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        _data: &FractalData,
+        _env: &Env,
+    ) {
+        if let LifeCycle::WidgetAdded = event {
+            ctx.request_anim_frame();
+        }
+    }
+
+    fn update(
+        &mut self,
+        _ctx: &mut UpdateCtx,
+        old_data: &FractalData,
+        data: &FractalData,
+        _env: &Env,
+    ) {
+        if let Some(c) = data.cursor {
+            if c != self.c || !old_data.params.same_orbits(&data.params) {
+                self.c = c;
+                // Drop the image of the previous c
+                self.image.fill(RGB::TRANSPARENT);
+            }
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &FractalData,
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &FractalData, _env: &Env) {
+        let focus = Circle::new(Point::ZERO, JULIA_RADIUS);
+        let params = self.params(data);
+        let renderer = self.renderer.get_or_insert_with(Renderer::with_recoloring);
+        renderer.resize(ctx.size() * RESOLUTION, Viewport::new(focus), &params);
+        let result = renderer.update(&mut self.image);
+
+        let rect = Rect::from_origin_size(Point::ORIGIN, ctx.size());
+        ctx.fill(rect, &Color::BLACK);
+        if !self.image.is_empty() {
+            RGB::create_image_data(&self.image, &mut self.image_data);
+            let image = ctx
+                .make_image(
+                    result.image_size.x,
+                    result.image_size.y,
+                    &self.image_data,
+                    ImageFormat::RgbaSeparate,
+                )
+                .unwrap();
+            ctx.draw_image(&image, rect, InterpolationMode::Bilinear);
+        }
+        let border = if ctx.is_hot() {
+            Color::WHITE
+        } else {
+            Color::WHITE.with_alpha(0.5)
+        };
+        ctx.stroke(rect, &border, 1.);
+    }
+}
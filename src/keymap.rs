@@ -0,0 +1,166 @@
+use crate::config::config_path;
+use crate::fractal::IterPolicy;
+use crate::location::parse_iterations;
+use druid::{Code, KeyEvent};
+use std::fs;
+
+const FILE_NAME: &str = "keys.conf";
+
+/// Bindings used when the config file does not override them
+const DEFAULT_KEYMAP: &str = "\
+ArrowLeft = pan_left
+ArrowRight = pan_right
+ArrowUp = pan_up
+ArrowDown = pan_down
+Equal = zoom_in
+Shift+Equal = zoom_in
+NumpadAdd = zoom_in
+Minus = zoom_out
+NumpadSubtract = zoom_out
+KeyR = reset
+Home = home
+Ctrl+KeyZ = undo
+Ctrl+Shift+KeyZ = redo
+Ctrl+KeyY = redo
+Alt+ArrowLeft = undo
+Alt+ArrowRight = redo
+Digit0 = iterations auto 223
+Digit1 = iterations fixed 100
+Digit2 = iterations fixed 250
+Digit3 = iterations fixed 500
+Digit4 = iterations fixed 1000
+Digit5 = iterations fixed 2500
+Digit6 = iterations fixed 5000
+Digit7 = iterations fixed 10000
+Digit8 = iterations fixed 25000
+Digit9 = iterations fixed 50000
+";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    /// Reset the view
+    Reset,
+    /// Reset the view and the render parameters
+    Home,
+    Undo,
+    Redo,
+    Iterations(IterPolicy),
+}
+
+impl Action {
+    fn parse(value: &str) -> Result<Self, String> {
+        let (name, arg) = value.split_once(' ').unwrap_or((value, ""));
+        match name {
+            "pan_left" => Ok(Action::PanLeft),
+            "pan_right" => Ok(Action::PanRight),
+            "pan_up" => Ok(Action::PanUp),
+            "pan_down" => Ok(Action::PanDown),
+            "zoom_in" => Ok(Action::ZoomIn),
+            "zoom_out" => Ok(Action::ZoomOut),
+            "reset" => Ok(Action::Reset),
+            "home" => Ok(Action::Home),
+            "undo" => Ok(Action::Undo),
+            "redo" => Ok(Action::Redo),
+            "iterations" => Ok(Action::Iterations(parse_iterations(arg.trim())?)),
+            _ => Err(format!("Unknown action '{}'", value)),
+        }
+    }
+}
+
+/// Key with its modifiers; Ctrl also matches Cmd on macOS
+#[derive(Clone, Copy, PartialEq)]
+struct Chord {
+    code: Code,
+    ctrl: bool,
+    shift: bool,
+    alt: bool,
+}
+
+impl Chord {
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut parts: Vec<&str> = value.split('+').map(|part| part.trim()).collect();
+        let key = parts.pop().unwrap_or("");
+        let code = key
+            .parse::<Code>()
+            .map_err(|_| format!("Unknown key '{}'", key))?;
+        let mut chord = Chord {
+            code: code,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        };
+        for modifier in parts {
+            match modifier {
+                "Ctrl" | "Cmd" => chord.ctrl = true,
+                "Shift" => chord.shift = true,
+                "Alt" => chord.alt = true,
+                _ => return Err(format!("Unknown modifier '{}'", modifier)),
+            }
+        }
+        Ok(chord)
+    }
+
+    fn from_event(event: &KeyEvent) -> Self {
+        Chord {
+            code: event.code,
+            ctrl: event.mods.ctrl() || event.mods.meta(),
+            shift: event.mods.shift(),
+            alt: event.mods.alt(),
+        }
+    }
+}
+
+pub struct Keymap {
+    bindings: Vec<(Chord, Action)>,
+}
+
+impl Keymap {
+    /// Default bindings, overridden by the user's config file if any
+    pub fn load() -> Self {
+        let mut keymap = Keymap {
+            bindings: Vec::new(),
+        };
+        keymap.apply(DEFAULT_KEYMAP);
+        if let Some(text) = config_path(FILE_NAME).and_then(|path| fs::read_to_string(path).ok()) {
+            keymap.apply(&text);
+        }
+        keymap
+    }
+
+    /// Add `key = action` lines, replacing the previous binding of a key
+    fn apply(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let binding = line
+                .split_once('=')
+                .ok_or_else(|| "Expected 'key = action'".to_string())
+                .and_then(|(key, action)| {
+                    Ok((Chord::parse(key.trim())?, Action::parse(action.trim())?))
+                });
+            match binding {
+                Ok((chord, action)) => {
+                    self.bindings.retain(|(other, _)| *other != chord);
+                    self.bindings.push((chord, action));
+                }
+                Err(err) => println!("Skipping key binding '{}': {}", line, err),
+            }
+        }
+    }
+
+    pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+        let chord = Chord::from_event(event);
+        self.bindings
+            .iter()
+            .find(|(other, _)| *other == chord)
+            .map(|(_, action)| *action)
+    }
+}
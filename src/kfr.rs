@@ -0,0 +1,194 @@
+//! Kalles Fraktaler `.kfr` location files
+use druid::kurbo::Circle;
+use druid::Point;
+
+use crate::fractal::{IterPolicy, RenderParams};
+use crate::image_utils::RGB;
+use crate::location::Location;
+use crate::palette::{ColorStop, Palette};
+use crate::viewport::Viewport;
+
+// Length of the Kalles Fraktaler color table
+const COLOR_TABLE: f64 = 1024.;
+
+/// Parse the `Re`, `Im`, `Zoom`, `Iterations`, `Colors` and `ColorOffset` entries
+pub fn parse(text: &str) -> Result<Location, String> {
+    let (mut re, mut im, mut zoom) = (None, None, None);
+    let mut params = RenderParams::new();
+    let mut offset = 0.;
+    for line in text.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let value = value.trim();
+        let number = || {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+                .ok_or_else(|| format!("Invalid {} '{}'", key.trim(), value))
+        };
+        match key.trim() {
+            "Re" => re = Some(number()?),
+            "Im" => im = Some(number()?),
+            "Zoom" => zoom = Some(number()?),
+            "Iterations" => {
+                params.iterations = IterPolicy {
+                    auto: false,
+                    count: number()? as usize,
+                }
+            }
+            "ColorOffset" => offset = number()? / COLOR_TABLE,
+            "Colors" => params.palette = parse_colors(value)?,
+            _ => {}
+        }
+    }
+    params.palette.offset = offset;
+    match (re, im, zoom) {
+        (Some(re), Some(im), Some(zoom)) if zoom > 0. => {
+            // A zoom of 1 shows a radius of 2
+            let view = Viewport::new(Circle::new(Point::new(re, im), 2. / zoom));
+            Ok(Location::new(&view, &params))
+        }
+        _ => Err("Missing or invalid Re, Im or Zoom".to_string()),
+    }
+}
+
+// Comma separated r,g,b triplets, evenly spread over the palette
+fn parse_colors(value: &str) -> Result<Palette, String> {
+    let channels = value
+        .split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| c.parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| format!("Invalid Colors '{}'", value))?;
+    let count = channels.len() / 3;
+    if count == 0 {
+        return Ok(Palette::new());
+    }
+    let stops = channels
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(k, c)| ColorStop {
+            pos: k as f64 / count as f64,
+            color: RGB {
+                r: c[0],
+                g: c[1],
+                b: c[2],
+                a: 255,
+            },
+        })
+        .collect();
+    Ok(Palette::from_stops(stops))
+}
+
+pub fn write(location: &Location) -> String {
+    // Resample the palette into evenly spaced colors
+    let mut palette = location.palette.clone();
+    palette.offset = 0.;
+    let count = location.palette.stops.len().max(16);
+    let colors: String = (0..count)
+        .map(|k| {
+            let c = palette.sample(k as f64 / count as f64);
+            format!("{},{},{},", c.r, c.g, c.b)
+        })
+        .collect();
+    let lines = [
+        format!("Re: {}", location.center.x),
+        format!("Im: {}", location.center.y),
+        format!("Zoom: {:E}", 2. / location.radius),
+        format!(
+            "Iterations: {}",
+            location.iterations.max_iter(location.radius)
+        ),
+        format!(
+            "ColorOffset: {}",
+            (location.palette.offset * COLOR_TABLE).round() as i64
+        ),
+        format!("Colors: {}", colors),
+    ];
+    lines.join("\r\n") + "\r\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::Formula;
+
+    // Trimmed Kalles Fraktaler 2 location file
+    const FIXTURE: &str = "Re: -1.7499576837060730192\r\n\
+        Im: -0.0000000000000000000\r\n\
+        Zoom: 2.5E10\r\n\
+        Iterations: 12000\r\n\
+        IterDiv: 1.000000\r\n\
+        SmoothMethod: 0\r\n\
+        ColorMethod: 0\r\n\
+        ColorOffset: 256\r\n\
+        Rotate: 0.000000\r\n\
+        Ratio: 360.000000\r\n\
+        Colors: 255,0,0,0,255,0,0,0,255,255,255,255,\r\n\
+        InteriorColor: 0,0,0,\r\n";
+
+    #[test]
+    fn parse_fixture() {
+        let location = parse(FIXTURE).unwrap();
+        assert!(location.center == Point::new(-1.7499576837060730192, 0.));
+        assert!((location.radius - 8e-11).abs() < 1e-24);
+        assert!(location.formula == Formula::Mandelbrot);
+        assert!(
+            location.iterations
+                == IterPolicy {
+                    auto: false,
+                    count: 12000,
+                }
+        );
+        assert_eq!(location.palette.offset, 0.25);
+        let stops = &location.palette.stops;
+        let positions: Vec<f64> = stops.iter().map(|stop| stop.pos).collect();
+        assert_eq!(positions, vec![0., 0.25, 0.5, 0.75]);
+        let colors: Vec<String> = stops.iter().map(|stop| stop.color.to_hex()).collect();
+        assert_eq!(colors, vec!["ff0000", "00ff00", "0000ff", "ffffff"]);
+    }
+
+    #[test]
+    fn zoom_to_radius() {
+        for (zoom, radius) in [("1", 2.), ("2", 1.), ("1E3", 2e-3), ("4.0e-1", 5.)] {
+            let text = format!("Re: 0\nIm: 0\nZoom: {}\n", zoom);
+            assert!((parse(&text).unwrap().radius - radius).abs() < 1e-12 * radius);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        for text in [
+            "Re: 0\r\nIm: 0\r\n",
+            "Re: 0\r\nIm: 0\r\nZoom: 0\r\n",
+            "Re: 0\r\nIm: 0\r\nZoom: -1\r\n",
+            "Re: x\r\nIm: 0\r\nZoom: 1\r\n",
+            "Re: 0\r\nIm: 0\r\nZoom: 1\r\nColors: 255,0,300,\r\n",
+        ] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+        // Missing colors keep the default palette
+        let location = parse("Re: 0\r\nIm: 0\r\nZoom: 1\r\nColors: ,\r\n").unwrap();
+        assert!(location.palette == Palette::new());
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let mut location = parse(FIXTURE).unwrap();
+        location.center.y = 1e-3;
+        let parsed = parse(&write(&location)).unwrap();
+        assert!(parsed.center == location.center);
+        assert!((parsed.radius / location.radius - 1.).abs() < 1e-12);
+        assert!(parsed.iterations == location.iterations);
+        assert_eq!(parsed.palette.offset, location.palette.offset);
+        // Resampled at the original stops
+        for k in 0..16 {
+            let t = k as f64 / 16.;
+            assert!(parsed.palette.sample(t) == location.palette.sample(t));
+        }
+    }
+}
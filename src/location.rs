@@ -0,0 +1,546 @@
+use druid::kurbo::Circle;
+use druid::{Data, Point};
+
+use crate::fractal::{
+    ColorMode, Formula, InteriorMode, IterPolicy, OrbitTrap, RenderParams, TrapShape,
+};
+use crate::image_utils::RGB;
+use crate::palette::{ColorStop, Palette};
+use crate::viewport::{Mat2, Viewport};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// A view and the parameters needed to reproduce it
+#[derive(Clone, PartialEq, Data)]
+pub struct Location {
+    pub center: Point,
+    pub radius: f64,
+    /// View rotation, in degrees
+    pub rotation: f64,
+    /// World side stretch of the view, see `Viewport`
+    pub skew: Mat2,
+    pub formula: Formula,
+    /// Constant c of the Julia formula
+    pub julia_c: Point,
+    pub iterations: IterPolicy,
+    pub escape_radius: f64,
+    pub palette: Palette,
+    pub color_mode: ColorMode,
+    pub interior: InteriorMode,
+    /// Orbit trap, its texture is only kept in memory
+    pub trap: OrbitTrap,
+    pub stripe_density: f64,
+    pub shading: bool,
+    pub light_angle: f64,
+    pub light_elevation: f64,
+    pub height: f64,
+}
+
+impl Location {
+    pub fn new(view: &Viewport, params: &RenderParams) -> Self {
+        Location {
+            center: view.focus.center,
+            radius: view.focus.radius,
+            rotation: view.rotation,
+            skew: view.skew,
+            formula: params.formula,
+            julia_c: params.julia_c,
+            iterations: params.iterations,
+            escape_radius: params.escape_radius,
+            palette: params.palette.clone(),
+            color_mode: params.color_mode,
+            interior: params.interior,
+            trap: params.trap.clone(),
+            stripe_density: params.stripe_density,
+            shading: params.shading,
+            light_angle: params.light_angle,
+            light_elevation: params.light_elevation,
+            height: params.height,
+        }
+    }
+
+    /// Set all the parameters but the projection
+    pub fn apply(&self, params: &mut RenderParams) {
+        params.formula = self.formula;
+        params.julia_c = self.julia_c;
+        params.iterations = self.iterations;
+        params.escape_radius = self.escape_radius;
+        params.palette = self.palette.clone();
+        params.color_mode = self.color_mode;
+        params.interior = self.interior;
+        // Keep the loaded texture if the location has none
+        let texture = self.trap.texture.clone().or(params.trap.texture.clone());
+        params.trap = OrbitTrap {
+            texture: texture,
+            ..self.trap.clone()
+        };
+        params.stripe_density = self.stripe_density;
+        params.shading = self.shading;
+        params.light_angle = self.light_angle;
+        params.light_elevation = self.light_elevation;
+        params.height = self.height;
+    }
+
+    pub fn view(&self) -> Viewport {
+        Viewport {
+            focus: Circle::new(self.center, self.radius),
+            rotation: self.rotation,
+            skew: self.skew,
+        }
+    }
+
+    /// Session file contents, one `key = value` pair per line
+    pub fn to_text(&self) -> String {
+        let iterations = if self.iterations.auto {
+            "auto"
+        } else {
+            "fixed"
+        };
+        let stops: Vec<String> = self
+            .palette
+            .stops
+            .iter()
+            .map(|stop| format!("{}:{}", stop.pos, stop.color.to_hex()))
+            .collect();
+        // Floats are printed with the shortest representation that round-trips
+        let mut lines = vec![
+            format!("re = {}", self.center.x),
+            format!("im = {}", self.center.y),
+            format!("radius = {}", self.radius),
+            format!("rotation = {}", self.rotation),
+            format!("formula = {}", self.formula.name()),
+            format!("iterations = {} {}", iterations, self.iterations.count),
+            format!("palette = {}", stops.join(" ")),
+            format!("palette_offset = {}", self.palette.offset),
+        ];
+        if self.formula == Formula::Julia {
+            lines.push(format!("julia_re = {}", self.julia_c.x));
+            lines.push(format!("julia_im = {}", self.julia_c.y));
+        }
+        if self.skew != Mat2::IDENTITY {
+            let skew = &self.skew;
+            lines.push(format!(
+                "skew = {} {} {} {}",
+                skew.a, skew.b, skew.c, skew.d
+            ));
+        }
+        // Coloring, only where it differs from the defaults
+        let default = RenderParams::new();
+        if self.escape_radius != default.escape_radius {
+            lines.push(format!("escape_radius = {}", self.escape_radius));
+        }
+        if self.color_mode != default.color_mode {
+            lines.push(format!("color_mode = {}", self.color_mode.name()));
+        }
+        if self.interior != default.interior {
+            lines.push(format!("interior = {}", self.interior.name()));
+        }
+        let trap = &self.trap;
+        if (trap.shape, trap.center, trap.angle, trap.radius)
+            != (
+                default.trap.shape,
+                default.trap.center,
+                default.trap.angle,
+                default.trap.radius,
+            )
+        {
+            lines.push(format!(
+                "trap = {} {} {} {} {}",
+                trap.shape.name(),
+                trap.center.x,
+                trap.center.y,
+                trap.angle,
+                trap.radius
+            ));
+        }
+        if self.stripe_density != default.stripe_density {
+            lines.push(format!("stripe_density = {}", self.stripe_density));
+        }
+        if self.shading {
+            lines.push("shading = on".to_string());
+        }
+        if (self.light_angle, self.light_elevation, self.height)
+            != (default.light_angle, default.light_elevation, default.height)
+        {
+            lines.push(format!(
+                "light = {} {} {}",
+                self.light_angle, self.light_elevation, self.height
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let (mut re, mut im, mut radius) = (None, None, None);
+        let mut rotation = 0.;
+        let mut skew = Mat2::IDENTITY;
+        let mut params = RenderParams::new();
+        let mut offset = 0.;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid line '{}'", line))?;
+            let value = value.trim();
+            match key.trim() {
+                "re" => re = Some(parse_f64(value)?),
+                "im" => im = Some(parse_f64(value)?),
+                "radius" => radius = Some(parse_f64(value)?),
+                "rotation" => rotation = parse_f64(value)?,
+                "skew" => skew = parse_skew(value)?,
+                "formula" => {
+                    params.formula = Formula::from_name(value)
+                        .ok_or_else(|| format!("Unknown formula '{}'", value))?
+                }
+                "julia_re" => params.julia_c.x = parse_f64(value)?,
+                "julia_im" => params.julia_c.y = parse_f64(value)?,
+                "iterations" => params.iterations = parse_iterations(value)?,
+                "escape_radius" => {
+                    params.escape_radius = parse_f64(value)?;
+                    if params.escape_radius < 2. {
+                        return Err(format!("Invalid escape radius '{}'", value));
+                    }
+                }
+                "palette" => params.palette = parse_palette(value)?,
+                "palette_offset" => offset = parse_f64(value)?,
+                "color_mode" => {
+                    params.color_mode = ColorMode::from_name(value)
+                        .ok_or_else(|| format!("Unknown color mode '{}'", value))?
+                }
+                "interior" => {
+                    params.interior = InteriorMode::from_name(value)
+                        .ok_or_else(|| format!("Unknown interior mode '{}'", value))?
+                }
+                "trap" => params.trap = parse_trap(value)?,
+                "stripe_density" => params.stripe_density = parse_f64(value)?,
+                "shading" => params.shading = value == "on",
+                "light" => match parse_floats(value)?[..] {
+                    [angle, elevation, height] => {
+                        params.light_angle = angle;
+                        params.light_elevation = elevation;
+                        params.height = height;
+                    }
+                    _ => return Err(format!("Invalid light '{}'", value)),
+                },
+                // Ignore keys from newer versions
+                _ => {}
+            }
+        }
+        params.palette.offset = offset;
+        match (re, im, radius) {
+            (Some(re), Some(im), Some(radius)) if radius > 0. => {
+                let view = Viewport {
+                    focus: Circle::new(Point::new(re, im), radius),
+                    rotation: rotation,
+                    skew: skew,
+                };
+                Ok(Location::new(&view, &params))
+            }
+            _ => Err("Missing or invalid re, im or radius".to_string()),
+        }
+    }
+
+    /// Compact URL-safe form of the session text
+    pub fn to_share_string(&self) -> String {
+        base64_encode(self.to_text().as_bytes())
+    }
+
+    pub fn from_share_string(share: &str) -> Result<Self, String> {
+        let bytes = base64_decode(share.trim())?;
+        let text = String::from_utf8(bytes).map_err(|_| "Invalid share string".to_string())?;
+        Self::from_text(&text)
+    }
+
+    /// Parse either a share string or a session text
+    pub fn parse(text: &str) -> Result<Self, String> {
+        // The share string alphabet has no '='
+        if text.contains('=') {
+            Self::from_text(text)
+        } else {
+            Self::from_share_string(text)
+        }
+    }
+}
+
+/// Four matrix coefficients, row by row, of an invertible matrix
+fn parse_skew(value: &str) -> Result<Mat2, String> {
+    let skew = match parse_floats(value)?[..] {
+        [a, b, c, d] => Mat2 {
+            a: a,
+            b: b,
+            c: c,
+            d: d,
+        },
+        _ => return Err(format!("Invalid skew '{}'", value)),
+    };
+    if skew.is_invertible() {
+        Ok(skew)
+    } else {
+        Err(format!("Singular skew '{}'", value))
+    }
+}
+
+/// Shape name, center, angle and radius
+fn parse_trap(value: &str) -> Result<OrbitTrap, String> {
+    let invalid = || format!("Invalid trap '{}'", value);
+    let (shape, rest) = value.split_once(' ').ok_or_else(invalid)?;
+    let shape = TrapShape::from_name(shape).ok_or_else(invalid)?;
+    match parse_floats(rest)?[..] {
+        [re, im, angle, radius] if radius > 0. => Ok(OrbitTrap {
+            shape: shape,
+            center: Point::new(re, im),
+            angle: angle,
+            radius: radius,
+            texture: None,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_floats(value: &str) -> Result<Vec<f64>, String> {
+    value.split_whitespace().map(parse_f64).collect()
+}
+
+fn parse_f64(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("Invalid number '{}'", value))
+}
+
+pub fn parse_iterations(value: &str) -> Result<IterPolicy, String> {
+    let invalid = || format!("Invalid iterations '{}'", value);
+    let (policy, count) = value.split_once(' ').ok_or_else(invalid)?;
+    let count = count.trim().parse::<usize>().map_err(|_| invalid())?;
+    match policy {
+        "auto" => Ok(IterPolicy {
+            auto: true,
+            count: count,
+        }),
+        "fixed" => Ok(IterPolicy {
+            auto: false,
+            count: count,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_palette(value: &str) -> Result<Palette, String> {
+    let invalid = || format!("Invalid palette '{}'", value);
+    let stops = value
+        .split_whitespace()
+        .map(|stop| {
+            let (pos, hex) = stop.split_once(':').ok_or_else(invalid)?;
+            Ok(ColorStop {
+                pos: parse_f64(pos)?.clamp(0., 1.),
+                color: RGB::from_hex(hex).ok_or_else(invalid)?,
+            })
+        })
+        .collect::<Result<Vec<ColorStop>, String>>()?;
+    // As many as the palette editor keeps
+    if stops.len() < 2 {
+        return Err(invalid());
+    }
+    Ok(Palette::from_stops(stops))
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (k, &b)| n | ((b as u32) << (16 - 8 * k)));
+        // Unpadded, a partial chunk of k bytes gives k + 1 characters
+        for k in 0..chunk.len() + 1 {
+            out.push(BASE64[((n >> (18 - 6 * k)) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let v = BASE64
+            .iter()
+            .position(|&b| b == c)
+            .ok_or_else(|| format!("Invalid character '{}'", c as char))?;
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Location {
+        let mut params = RenderParams::new();
+        params.formula = Formula::Julia;
+        params.julia_c = Point::new(-0.8, 0.156);
+        params.iterations = IterPolicy {
+            auto: false,
+            count: 5000,
+        };
+        params.escape_radius = 64.;
+        params.palette = Palette::from_stops(vec![
+            ColorStop {
+                pos: 0.,
+                color: RGB::from_hex("102030").unwrap(),
+            },
+            ColorStop {
+                pos: 0.3,
+                color: RGB::from_hex("ff8000").unwrap(),
+            },
+        ]);
+        params.palette.offset = 0.125;
+        params.color_mode = ColorMode::TrapMin;
+        params.interior = InteriorMode::AtomDomain;
+        params.trap = OrbitTrap {
+            shape: TrapShape::Cross,
+            center: Point::new(0.5, -0.25),
+            angle: 30.,
+            radius: 0.1,
+            texture: None,
+        };
+        params.stripe_density = 7.5;
+        params.shading = true;
+        params.light_angle = 120.;
+        params.light_elevation = 30.;
+        params.height = 2.;
+        let view = Viewport {
+            focus: Circle::new(Point::new(-0.743643887037151, 0.131825904205330), 1.2e-11),
+            rotation: 33.5,
+            skew: Mat2 {
+                a: 1.5,
+                b: 0.25,
+                c: -0.1,
+                d: 0.8,
+            },
+        };
+        Location::new(&view, &params)
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let location = sample();
+        assert!(Location::from_text(&location.to_text()).unwrap() == location);
+        // Defaults are not written
+        let location = Location::new(&sample().view(), &RenderParams::new());
+        let location = Location {
+            skew: Mat2::IDENTITY,
+            ..location
+        };
+        let text = location.to_text();
+        assert_eq!(text.lines().count(), 8);
+        assert!(Location::from_text(&text).unwrap() == location);
+    }
+
+    #[test]
+    fn apply_restores_params() {
+        let location = sample();
+        let mut params = RenderParams::new();
+        location.apply(&mut params);
+        assert!(Location::new(&location.view(), &params) == location);
+    }
+
+    #[test]
+    fn share_round_trip() {
+        let location = sample();
+        let share = location.to_share_string();
+        assert!(!share.contains('='));
+        assert!(Location::from_share_string(&share).unwrap() == location);
+        assert!(Location::parse(&share).unwrap() == location);
+        assert!(Location::parse(&location.to_text()).unwrap() == location);
+    }
+
+    #[test]
+    fn base64() {
+        for (text, encoded) in [("", ""), ("M", "TQ"), ("Ma", "TWE"), ("Man", "TWFu")] {
+            assert_eq!(base64_encode(text.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), text.as_bytes());
+        }
+        // URL-safe alphabet
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(base64_decode("-_8").unwrap(), vec![0xfb, 0xff]);
+        assert!(base64_decode("TW+u").is_err());
+        for len in 0..64 {
+            let bytes: Vec<u8> = (0..len).map(|k| (k * 37 % 256) as u8).collect();
+            assert_eq!(base64_decode(&base64_encode(&bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn defaults_and_unknown_keys() {
+        let text = "# comment\nre = 0.25\n\nim = -1\nradius = 0.5\nfuture_key = 1\n";
+        let location = Location::from_text(text).unwrap();
+        assert!(location.center == Point::new(0.25, -1.));
+        assert_eq!(location.radius, 0.5);
+        assert_eq!(location.rotation, 0.);
+        assert!(location.skew == Mat2::IDENTITY);
+        let mut params = RenderParams::new();
+        location.apply(&mut params);
+        assert!(params == RenderParams::new());
+    }
+
+    #[test]
+    fn invalid_text() {
+        let base = "re = 0\nim = 0\n";
+        for text in [
+            // Missing or invalid radius
+            base.to_string(),
+            format!("{}radius = 0", base),
+            format!("{}radius = -1", base),
+            format!("{}radius = inf", base),
+            format!("{}radius = x", base),
+            // Lines and values
+            format!("{}radius = 1\nno separator", base),
+            format!("{}radius = 1\nformula = unknown", base),
+            format!("{}radius = 1\niterations = 100", base),
+            format!("{}radius = 1\niterations = some 100", base),
+            format!("{}radius = 1\npalette = 0.5:ff00", base),
+            format!("{}radius = 1\npalette = 0.5", base),
+            format!("{}radius = 1\npalette = nan:ff0000 1:0000ff", base),
+            format!("{}radius = 1\npalette = 0:ff0000 inf:0000ff", base),
+            format!("{}radius = 1\npalette = 0:ff0000", base),
+            format!("{}radius = 1\npalette = ", base),
+            // Singular or incomplete skew
+            format!("{}radius = 1\nskew = 1 2 2 4", base),
+            format!("{}radius = 1\nskew = 1 0 0", base),
+            format!("{}radius = 1\nskew = 1 0 0 nan", base),
+            // Coloring
+            format!("{}radius = 1\nescape_radius = 1", base),
+            format!("{}radius = 1\ncolor_mode = plasma", base),
+            format!("{}radius = 1\ninterior = white", base),
+            format!("{}radius = 1\ntrap = star 0 0 0 1", base),
+            format!("{}radius = 1\ntrap = circle 0 0 0 0", base),
+            format!("{}radius = 1\ntrap = circle 0 0 0", base),
+            format!("{}radius = 1\nlight = 45 45", base),
+        ] {
+            assert!(Location::from_text(&text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn palette_stops() {
+        let text = "re = 0\nim = 0\nradius = 1\npalette = 0.5:00ff00 0:ff0000 2:0000ff\n";
+        let stops = Location::from_text(text).unwrap().palette.stops;
+        let positions: Vec<f64> = stops.iter().map(|stop| stop.pos).collect();
+        // Sorted and clamped to [0, 1]
+        assert_eq!(positions, vec![0., 0.5, 1.]);
+        assert_eq!(stops[0].color.to_hex(), "ff0000");
+        assert_eq!(stops[2].color.to_hex(), "0000ff");
+    }
+}
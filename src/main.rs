@@ -1,20 +1,73 @@
+mod bookmarks;
+mod config;
+mod delegate;
 mod fractal;
 mod fractal_widget;
 mod image_utils;
+mod julia_preview;
+mod keymap;
+mod kfr;
+mod location;
+mod overview;
+mod palette;
+mod palette_editor;
+mod params_panel;
 mod progress_bar;
 mod renderer;
 mod stack_widget;
 mod time;
+mod video;
+mod viewport;
 use progress_bar::ProgressBar;
 use wasm_bindgen::prelude::*;
 
+use bookmarks::Thumbnails;
+use delegate::Delegate;
 use druid::{
-    widget::{Button, Flex, Label, SizedBox},
-    AppLauncher, Color, FontDescriptor, FontFamily, FontStyle, UnitPoint, Widget, WidgetExt,
-    WindowDesc,
+    commands,
+    kurbo::Circle,
+    widget::{Button, Either, Flex, Label, SizedBox, ViewSwitcher},
+    AppLauncher, Application, Color, FileDialogOptions, FileSpec, FontDescriptor, FontFamily,
+    FontStyle, UnitPoint, Widget, WidgetExt, WindowDesc,
 };
-use fractal_widget::{FractalData, FractalWidget};
+use fractal::Formula;
+use fractal_widget::{zoom_factor_str, FractalData, FractalWidget, HistoryEntry};
+use julia_preview::JuliaPreview;
+use location::Location;
+use overview::Overview;
 use stack_widget::{StackAlign, StackWidget};
+use std::sync::Arc;
+
+// Number of history views shown in the breadcrumb
+const BREADCRUMBS: usize = 8;
+
+fn build_breadcrumbs() -> impl Widget<FractalData> {
+    ViewSwitcher::new(
+        |data: &FractalData, _env| data.history(),
+        |history: &(Arc<Vec<HistoryEntry>>, usize), _data, _env| {
+            let (entries, current) = history;
+            let start = current.saturating_sub(BREADCRUMBS / 2);
+            let end = (start + BREADCRUMBS).min(entries.len());
+            let mut row = Flex::row();
+            for idx in start..end {
+                let text = zoom_factor_str(entries[idx].view.focus.radius);
+                if idx == *current {
+                    row.add_child(Label::new(text).padding(5.0));
+                } else {
+                    let button =
+                        Button::new(text).on_click(move |_ctx, data: &mut FractalData, _env| {
+                            data.history_jump(idx);
+                        });
+                    row.add_child(button);
+                }
+                if idx + 1 < end {
+                    row.add_child(Label::new(">"));
+                }
+            }
+            Box::new(row)
+        },
+    )
+}
 
 pub fn build_gui() -> impl Widget<FractalData> {
     let fractal_widget = FractalWidget::new();
@@ -34,6 +87,94 @@ pub fn build_gui() -> impl Widget<FractalData> {
         .on_click(|_ctx, data, _env| {
             data.zoom_reset();
         });
+    let undo = Button::<FractalData>::new("undo")
+        .padding(5.0)
+        .on_click(|_ctx, data, _env| {
+            data.undo();
+        })
+        .disabled_if(|data, _env| !data.can_undo());
+    let redo = Button::<FractalData>::new("redo")
+        .padding(5.0)
+        .on_click(|_ctx, data, _env| {
+            data.redo();
+        })
+        .disabled_if(|data, _env| !data.can_redo());
+    let copy = Button::<FractalData>::new("copy")
+        .padding(5.0)
+        .on_click(|_ctx, data, _env| {
+            let share = data.location().to_share_string();
+            Application::global().clipboard().put_string(share);
+        });
+    let paste = Button::<FractalData>::new("paste")
+        .padding(5.0)
+        .on_click(|_ctx, data, _env| {
+            if let Some(text) = Application::global().clipboard().get_string() {
+                match Location::parse(&text) {
+                    Ok(location) => data.set_location(&location),
+                    Err(err) => println!("Invalid location: {}", err),
+                }
+            }
+        });
+    let session = FileSpec::new("Fractal session", &["fractal"]);
+    let kfr_file = FileSpec::new("Kalles Fraktaler location", &["kfr"]);
+    let save = Button::<FractalData>::new("save")
+        .padding(5.0)
+        .on_click(move |ctx, _data, _env| {
+            let options = FileDialogOptions::new()
+                .allowed_types(vec![session, kfr_file])
+                .default_type(session)
+                .default_name("location.fractal");
+            ctx.submit_command(commands::SHOW_SAVE_PANEL.with(options));
+        });
+    let open = Button::<FractalData>::new("open")
+        .padding(5.0)
+        .on_click(move |ctx, _data, _env| {
+            let options = FileDialogOptions::new().allowed_types(vec![session, kfr_file]);
+            ctx.submit_command(commands::SHOW_OPEN_PANEL.with(options));
+        });
+    let show_bookmarks = Button::<bool>::new("bookmarks")
+        .padding(5.0)
+        .on_click(|_ctx, show, _env| {
+            *show = !*show;
+        })
+        .lens(FractalData::show_bookmarks);
+    let show_inspector = Button::<bool>::new("inspect")
+        .padding(5.0)
+        .on_click(|_ctx, show, _env| {
+            *show = !*show;
+        })
+        .lens(FractalData::show_inspector);
+    let show_stats = Button::<bool>::new("stats")
+        .padding(5.0)
+        .on_click(|_ctx, show, _env| {
+            *show = !*show;
+        })
+        .lens(FractalData::show_stats);
+    let orbit = Button::<FractalData>::dynamic(|data, _env| {
+        format!("orbit: {}", data.orbit_display.name())
+    })
+    .padding(5.0)
+    .on_click(|_ctx, data, _env| {
+        data.orbit_display = data.orbit_display.next();
+    });
+    let show_julia = Button::<bool>::new("julia")
+        .padding(5.0)
+        .on_click(|_ctx, show, _env| {
+            *show = !*show;
+        })
+        .lens(FractalData::show_julia);
+    let show_params = Button::<bool>::new("params")
+        .padding(5.0)
+        .on_click(|_ctx, show, _env| {
+            *show = !*show;
+        })
+        .lens(FractalData::show_params);
+    let show_overview = Button::<bool>::new("overview")
+        .padding(5.0)
+        .on_click(|_ctx, show, _env| {
+            *show = !*show;
+        })
+        .lens(FractalData::show_overview);
     let label = Label::new(|data: &FractalData, _: &_| data.zoom_factor_str());
 
     let font = FontDescriptor::new(FontFamily::SANS_SERIF).with_style(FontStyle::Italic);
@@ -50,6 +191,19 @@ pub fn build_gui() -> impl Widget<FractalData> {
         .with_child(zoom_in)
         .with_child(zoom_out)
         .with_child(reset)
+        .with_child(undo)
+        .with_child(redo)
+        .with_child(copy)
+        .with_child(paste)
+        .with_child(save)
+        .with_child(open)
+        .with_child(show_params)
+        .with_child(show_bookmarks)
+        .with_child(show_inspector)
+        .with_child(show_stats)
+        .with_child(orbit)
+        .with_child(show_julia)
+        .with_child(show_overview)
         .with_child(label)
         .with_flex_spacer(1.)
         .with_child(credits)
@@ -57,14 +211,94 @@ pub fn build_gui() -> impl Widget<FractalData> {
 
     let toolbar = Flex::column()
         .with_child(progress_bar)
+        .with_child(build_breadcrumbs().padding((10., 5., 10., 0.)))
         .with_child(button_bar.expand_width())
         .background(Color::rgba8(0, 0, 0, 128));
 
+    let params_panel = Either::new(
+        |data: &FractalData, _env| data.show_params,
+        params_panel::build_panel().fix_width(260.),
+        SizedBox::empty(),
+    );
+    let side_panel = Either::new(
+        |data: &FractalData, _env| data.show_bookmarks,
+        bookmarks::build_panel().fix_width(200.),
+        SizedBox::empty(),
+    );
+
+    // Cursor inspector
+    let inspector = Either::new(
+        |data: &FractalData, _env| data.inspection.is_some(),
+        Label::new(|data: &FractalData, _: &_| {
+            data.inspection
+                .as_ref()
+                .map(|inspection| inspection.text())
+                .unwrap_or_default()
+        })
+        .with_text_size(12.)
+        .padding(5.)
+        .background(Color::BLACK.with_alpha(0.6))
+        .rounded(4.),
+        SizedBox::empty(),
+    );
+    // Render timing and throughput
+    let stats = Either::new(
+        |data: &FractalData, _env| data.show_stats,
+        Label::new(|data: &FractalData, _: &_| data.stats.text())
+            .with_text_size(12.)
+            .padding(5.)
+            .background(Color::BLACK.with_alpha(0.6))
+            .rounded(4.)
+            .padding(10.),
+        SizedBox::empty(),
+    );
+    // Julia set of the point under the cursor, rebuilt when shown so that
+    // its renderer only runs while visible
+    let julia_preview = ViewSwitcher::new(
+        |data: &FractalData, _env| data.show_julia && data.params.formula == Formula::Mandelbrot,
+        |shown: &bool, _data, _env| -> Box<dyn Widget<FractalData>> {
+            if *shown {
+                Box::new(JuliaPreview::new().fix_size(160., 160.).padding(10.))
+            } else {
+                Box::new(SizedBox::empty())
+            }
+        },
+    );
+    // Full set with the current view, rebuilt when shown like the Julia preview
+    let overview = ViewSwitcher::new(
+        |data: &FractalData, _env| data.show_overview,
+        |shown: &bool, _data, _env| -> Box<dyn Widget<FractalData>> {
+            if !*shown {
+                return Box::new(SizedBox::empty());
+            }
+            let overview = Flex::column()
+                .with_child(Overview::new().fix_size(160., 166.))
+                .with_child(
+                    Label::new(|data: &FractalData, _: &_| {
+                        format!("depth 10^{:.1}", data.zoom_depth())
+                    })
+                    .with_text_size(10.),
+                )
+                .padding(10.);
+            Box::new(overview)
+        },
+    );
+    let view = StackWidget::new()
+        .with_child(fractal_widget.expand(), StackAlign::TopLeft)
+        .with_child(julia_preview, StackAlign::BottomRight)
+        .with_child(overview, StackAlign::TopRight)
+        .with_child(stats, StackAlign::TopLeft)
+        .with_child(inspector, StackAlign::Cursor);
+
     // widget
-    Flex::column()
-        .with_flex_child(fractal_widget.expand(), 1.0)
-        .with_child(toolbar)
+    let main = Flex::column()
+        .with_flex_child(view, 1.0)
+        .with_child(toolbar);
     // .with_child(button)
+    Flex::row()
+        .with_flex_child(main, 1.0)
+        .with_child(params_panel)
+        .with_child(side_panel)
 }
 
 // Wasm wrapper
@@ -76,14 +310,28 @@ pub fn wasm_main() {
 }
 
 pub fn main() {
+    // Headless zoom video rendering
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("video") {
+        if let Err(err) = video::run(&args[2..]) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
     // RGB::resize_image(&[], IPoint::default(), &mut Vec::new(), IPoint::new(3, 2));
     let window = WindowDesc::new(build_gui())
         .title("Fractal renderer")
         // .window_size((512., 256.));
         .window_size((512., 512.));
     // let data = HSL::new();
-    AppLauncher::with_window(window)
+    let data = FractalData::new();
+    let launcher = AppLauncher::with_window(window);
+    let sink = launcher.get_external_handle();
+    let thumbnails = Thumbnails::new(sink.clone());
+    launcher
+        .delegate(Delegate::new(sink, thumbnails, &data.bookmarks))
         .log_to_console()
-        .launch(FractalData::new())
+        .launch(data)
         .expect("launch failed");
 }
@@ -0,0 +1,190 @@
+use crate::fractal_widget::FractalData;
+use crate::image_utils::{IPoint, RGB};
+use crate::renderer::Renderer;
+use crate::viewport::Viewport;
+use druid::kurbo::{BezPath, Line, Rect, Shape};
+use druid::piet::{ImageFormat, InterpolationMode};
+use druid::widget::prelude::*;
+use druid::{Color, Point};
+
+// Height of the zoom depth bar below the map
+const BAR_HEIGHT: f64 = 6.;
+// Deepest zoom of the depth bar, in decades, about the limit of f64 precision
+const MAX_DEPTH: f64 = 14.;
+// Viewports smaller than this, in pixels, are marked with a crosshair
+const CROSSHAIR_SIZE: f64 = 6.;
+
+/// Minimap of the full set, marking the current view
+pub struct Overview {
+    // Created on the first paint
+    renderer: Option<Renderer>,
+    image: Vec<RGB>,
+    image_data: Vec<u8>,
+    image_size: IPoint,
+    // Whether the map is fully rendered, painted again on data changes only
+    complete: bool,
+}
+
+impl Overview {
+    pub fn new() -> Self {
+        Overview {
+            renderer: None,
+            image: Vec::new(),
+            image_data: Vec::new(),
+            image_size: IPoint::new(0, 0),
+            complete: false,
+        }
+    }
+
+    fn map_size(size: Size) -> Size {
+        Size::new(size.width, (size.height - BAR_HEIGHT).max(0.))
+    }
+}
+
+impl Widget<FractalData> for Overview {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut FractalData, _env: &Env) {
+        match event {
+            Event::MouseDown(mouse) => {
+                let map_size = Self::map_size(ctx.size());
+                if mouse.pos.y < map_size.height {
+                    let home = Viewport::new(data.home_focus());
+                    data.pan_to(home.px_to_world(map_size, mouse.pos));
+                }
+                ctx.set_handled();
+            }
+            // Keep the main view from reacting under the minimap
+            Event::MouseUp(_) | Event::Wheel(_) => ctx.set_handled(),
+            Event::AnimFrame(_interval) => {
+                if !self.complete {
+                    ctx.request_anim_frame();
+                    ctx.request_paint();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        _data: &FractalData,
+        _env: &Env,
+    ) {
+        if let LifeCycle::WidgetAdded = event {
+            ctx.request_anim_frame();
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &FractalData,
+        data: &FractalData,
+        _env: &Env,
+    ) {
+        if !old_data.params.same_orbits(&data.params) {
+            // Drop the stale map, the renderer starts over
+            self.image.fill(RGB::TRANSPARENT);
+        }
+        // Render any change, recolorings included, then idle again
+        self.complete = false;
+        ctx.request_anim_frame();
+        ctx.request_paint();
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &FractalData,
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &FractalData, _env: &Env) {
+        let size = ctx.size();
+        let map_size = Self::map_size(size);
+        let home = Viewport::new(data.home_focus());
+        // The renderer keeps the full set once complete
+        let renderer = self.renderer.get_or_insert_with(Renderer::with_recoloring);
+        renderer.resize(map_size, home, &data.params);
+        let result = renderer.update(&mut self.image);
+        self.image_size = result.image_size;
+        self.complete = result.complete && !renderer.is_recoloring();
+
+        let map_rect = Rect::from_origin_size(Point::ORIGIN, map_size);
+        ctx.fill(map_rect, &Color::BLACK);
+        if !self.image.is_empty() {
+            RGB::create_image_data(&self.image, &mut self.image_data);
+            let image = ctx
+                .make_image(
+                    self.image_size.x,
+                    self.image_size.y,
+                    &self.image_data,
+                    ImageFormat::RgbaSeparate,
+                )
+                .unwrap();
+            ctx.draw_image(&image, map_rect, InterpolationMode::Bilinear);
+        }
+
+        // Corners of the main view, with its aspect ratio, rotation and skew
+        let current = data.view();
+        // Square until the main view is laid out
+        let view_size = if data.view_size.is_empty() {
+            Size::new(1., 1.)
+        } else {
+            data.view_size
+        };
+        let (w, h) = (view_size.width, view_size.height);
+        let corners: Vec<Point> = [(0., 0.), (w, 0.), (w, h), (0., h)]
+            .iter()
+            .map(|&(x, y)| {
+                let world = current.px_to_world(view_size, Point::new(x, y));
+                home.world_to_px(map_size, world)
+            })
+            .collect();
+        let mut view = BezPath::new();
+        view.move_to(corners[0]);
+        for &corner in &corners[1..] {
+            view.line_to(corner);
+        }
+        view.close_path();
+        let bounds = view.bounding_box();
+        let marker_color = Color::WHITE;
+        if bounds.width() < CROSSHAIR_SIZE {
+            let center = bounds.center();
+            ctx.with_save(|ctx| {
+                ctx.clip(map_rect);
+                ctx.stroke(
+                    Line::new((center.x, 0.), (center.x, map_size.height)),
+                    &marker_color.clone().with_alpha(0.6),
+                    1.,
+                );
+                ctx.stroke(
+                    Line::new((0., center.y), (map_size.width, center.y)),
+                    &marker_color.clone().with_alpha(0.6),
+                    1.,
+                );
+            });
+        }
+        ctx.stroke(view, &marker_color, 1.);
+        ctx.stroke(map_rect, &Color::WHITE.with_alpha(0.5), 1.);
+
+        // Zoom depth, on a log scale with a tick every decade
+        let bar = Rect::new(0., map_size.height, size.width, size.height);
+        ctx.fill(bar, &Color::GRAY);
+        let depth = (data.zoom_depth() / MAX_DEPTH).clamp(0., 1.);
+        let filled = Rect::new(0., bar.y0, depth * bar.width(), bar.y1);
+        ctx.fill(filled, &Color::BLUE.with_alpha(0.7));
+        for decade in 1..MAX_DEPTH as usize {
+            let x = decade as f64 / MAX_DEPTH * bar.width();
+            ctx.stroke(
+                Line::new((x, bar.y0), (x, bar.y1)),
+                &Color::BLACK.with_alpha(0.4),
+                1.,
+            );
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use druid::{Data, Lens};
+
+use crate::image_utils::RGB;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorStop {
+    /// Position in [0, 1]
+    pub pos: f64,
+    pub color: RGB,
+}
+
+/// Cyclic color gradient
+#[derive(Clone, PartialEq, Data, Lens)]
+pub struct Palette {
+    /// Stops sorted by position
+    pub stops: Arc<Vec<ColorStop>>,
+    /// Shift applied to the gradient coordinate
+    pub offset: f64,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        // Hue cycle, with a brightness ramp at the start
+        let positions = [
+            0.,
+            0.025,
+            0.05,
+            0.075,
+            0.1,
+            1. / 6.,
+            2. / 6.,
+            0.5,
+            4. / 6.,
+            5. / 6.,
+            0.999,
+        ];
+        let stops = positions
+            .iter()
+            .map(|&t| {
+                let hsv = RGB::from_hsv(360. * t, 1., 10. * t);
+                ColorStop {
+                    pos: t,
+                    color: RGB {
+                        r: hsv.b,
+                        g: hsv.g,
+                        b: hsv.r,
+                        a: 255,
+                    },
+                }
+            })
+            .collect();
+        Palette::from_stops(stops)
+    }
+
+    pub fn from_stops(mut stops: Vec<ColorStop>) -> Self {
+        stops.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+        Palette {
+            stops: Arc::new(stops),
+            offset: 0.,
+        }
+    }
+
+    /// Color at `t`, wrapping around [0, 1)
+    pub fn sample(&self, t: f64) -> RGB {
+        let stops = &self.stops;
+        if stops.is_empty() {
+            return RGB::BLACK;
+        }
+        let t = (t + self.offset).rem_euclid(1.);
+        let next = stops.iter().position(|s| s.pos > t).unwrap_or(stops.len());
+        // Neighbouring stops, wrapping around the ends
+        let shifted = |stop: ColorStop, dt: f64| ColorStop {
+            pos: stop.pos + dt,
+            color: stop.color,
+        };
+        let (a, b) = if next == 0 {
+            (shifted(stops[stops.len() - 1], -1.), stops[0])
+        } else if next == stops.len() {
+            (stops[next - 1], shifted(stops[0], 1.))
+        } else {
+            (stops[next - 1], stops[next])
+        };
+        if b.pos <= a.pos {
+            return a.color;
+        }
+        a.color.interpolate(&b.color, (t - a.pos) / (b.pos - a.pos))
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use druid::kurbo::Rect;
+use druid::widget::prelude::*;
+use druid::{Color, MouseButton, Point};
+
+use crate::image_utils::RGB;
+use crate::palette::{ColorStop, Palette};
+
+// Horizontal inset, keeping the end stops grabbable
+const MARGIN: f64 = 6.;
+const GRADIENT_HEIGHT: f64 = 24.;
+const MARKER_HEIGHT: f64 = 10.;
+const BAR_HEIGHT: f64 = 14.;
+const GAP: f64 = 6.;
+// Distance within which a click grabs a stop, in pixels
+const GRAB_DISTANCE: f64 = 6.;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Drag {
+    Stop,
+    Hue,
+    Saturation,
+    Value,
+}
+
+/// Gradient editor with draggable stops and an HSV picker for the selected one.
+/// Click the gradient to add a stop, right click a stop to remove it.
+pub struct PaletteEditor {
+    selected: Option<usize>,
+    drag: Option<Drag>,
+    // Picker color, kept apart so that the hue survives grays
+    hsv: (f64, f64, f64),
+}
+
+impl PaletteEditor {
+    pub fn new() -> Self {
+        PaletteEditor {
+            selected: None,
+            drag: None,
+            hsv: (0., 0., 0.),
+        }
+    }
+
+    fn bar_top(idx: usize) -> f64 {
+        GRADIENT_HEIGHT + MARKER_HEIGHT + GAP + idx as f64 * (BAR_HEIGHT + GAP)
+    }
+
+    fn pos_to_x(size: Size, pos: f64) -> f64 {
+        MARGIN + pos * (size.width - 2. * MARGIN)
+    }
+
+    fn x_to_pos(size: Size, x: f64) -> f64 {
+        ((x - MARGIN) / (size.width - 2. * MARGIN)).clamp(0., 1.)
+    }
+
+    fn stop_at(palette: &Palette, size: Size, x: f64) -> Option<usize> {
+        palette
+            .stops
+            .iter()
+            .enumerate()
+            .map(|(idx, stop)| (idx, (Self::pos_to_x(size, stop.pos) - x).abs()))
+            .filter(|(_, distance)| *distance < GRAB_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(idx, _)| idx)
+    }
+
+    fn select(&mut self, palette: &Palette, idx: usize) {
+        self.selected = Some(idx);
+        self.hsv = palette.stops[idx].color.to_hsv();
+    }
+
+    /// Color of the gradient at a stop position, ignoring the offset
+    fn color_at(palette: &Palette, pos: f64) -> RGB {
+        palette.sample(pos - palette.offset)
+    }
+
+    fn add_stop(&mut self, palette: &mut Palette, pos: f64) {
+        let stop = ColorStop {
+            pos: pos,
+            color: Self::color_at(palette, pos),
+        };
+        let stops = Arc::make_mut(&mut palette.stops);
+        let idx = stops
+            .iter()
+            .position(|s| s.pos > pos)
+            .unwrap_or(stops.len());
+        stops.insert(idx, stop);
+        self.select(palette, idx);
+    }
+
+    fn remove_stop(&mut self, palette: &mut Palette, idx: usize) {
+        // A gradient needs two stops
+        if palette.stops.len() > 2 {
+            Arc::make_mut(&mut palette.stops).remove(idx);
+            self.selected = None;
+        }
+    }
+
+    fn apply_drag(&mut self, palette: &mut Palette, size: Size, pos: Point) {
+        let selected = match self.selected {
+            Some(selected) => selected,
+            None => return,
+        };
+        let t = Self::x_to_pos(size, pos.x);
+        match self.drag {
+            Some(Drag::Stop) => {
+                let stops = Arc::make_mut(&mut palette.stops);
+                let mut stop = stops.remove(selected);
+                stop.pos = t;
+                // Keep the stops sorted, following the dragged one
+                let idx = stops.iter().position(|s| s.pos > t).unwrap_or(stops.len());
+                stops.insert(idx, stop);
+                self.selected = Some(idx);
+                return;
+            }
+            Some(Drag::Hue) => self.hsv.0 = t * 360.,
+            Some(Drag::Saturation) => self.hsv.1 = t,
+            Some(Drag::Value) => self.hsv.2 = t,
+            None => return,
+        }
+        let (h, s, v) = self.hsv;
+        // from_hsv expects a hue below 360
+        Arc::make_mut(&mut palette.stops)[selected].color = RGB::from_hsv(h.min(359.9), s, v);
+    }
+}
+
+fn color(rgb: RGB) -> Color {
+    Color::rgba8(rgb.r, rgb.g, rgb.b, rgb.a)
+}
+
+impl Widget<Palette> for PaletteEditor {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Palette, _env: &Env) {
+        let size = ctx.size();
+        match event {
+            Event::MouseDown(mouse) => {
+                ctx.set_active(true);
+                let stop = Self::stop_at(data, size, mouse.pos.x);
+                if mouse.pos.y < GRADIENT_HEIGHT + MARKER_HEIGHT {
+                    match stop {
+                        Some(idx) if mouse.button == MouseButton::Right => {
+                            self.remove_stop(data, idx)
+                        }
+                        Some(idx) => {
+                            self.select(data, idx);
+                            self.drag = Some(Drag::Stop);
+                        }
+                        None if mouse.button == MouseButton::Left => {
+                            self.add_stop(data, Self::x_to_pos(size, mouse.pos.x));
+                            self.drag = Some(Drag::Stop);
+                        }
+                        None => (),
+                    }
+                } else {
+                    let bars = [Drag::Hue, Drag::Saturation, Drag::Value];
+                    self.drag = bars
+                        .iter()
+                        .enumerate()
+                        .find(|(idx, _)| {
+                            let top = Self::bar_top(*idx);
+                            mouse.pos.y >= top && mouse.pos.y < top + BAR_HEIGHT
+                        })
+                        .map(|(_, drag)| *drag);
+                    self.apply_drag(data, size, mouse.pos);
+                }
+                ctx.request_paint();
+            }
+            Event::MouseMove(mouse) => {
+                if ctx.is_active() {
+                    self.apply_drag(data, size, mouse.pos);
+                    ctx.request_paint();
+                }
+            }
+            Event::MouseUp(_) => {
+                ctx.set_active(false);
+                self.drag = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &Palette,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Palette, data: &Palette, _env: &Env) {
+        if !old_data.same(data) {
+            // The palette may have been replaced, by a loaded location for instance
+            if let Some(selected) = self.selected {
+                if selected >= data.stops.len() {
+                    self.selected = None;
+                }
+            }
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &Palette,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain(Size::new(bc.max().width, Self::bar_top(3)))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Palette, _env: &Env) {
+        let size = ctx.size();
+        let width = (size.width - 2. * MARGIN).max(1.);
+        let columns = width as usize;
+        // Gradient preview
+        for k in 0..columns {
+            let t = k as f64 / width;
+            let x = MARGIN + k as f64;
+            let rect = Rect::new(x, 0., x + 1., GRADIENT_HEIGHT);
+            ctx.fill(rect, &color(Self::color_at(data, t)));
+        }
+        // Stops
+        for (idx, stop) in data.stops.iter().enumerate() {
+            let x = Self::pos_to_x(size, stop.pos);
+            let marker = Rect::new(
+                x - 3.,
+                GRADIENT_HEIGHT,
+                x + 3.,
+                GRADIENT_HEIGHT + MARKER_HEIGHT,
+            );
+            ctx.fill(marker, &color(stop.color));
+            let outline = if self.selected == Some(idx) {
+                Color::WHITE
+            } else {
+                Color::grey(0.5)
+            };
+            ctx.stroke(marker, &outline, 1.);
+        }
+
+        // Picker bars of the selected stop
+        if self.selected.is_none() {
+            return;
+        }
+        let (h, s, v) = self.hsv;
+        let bars: [(f64, &dyn Fn(f64) -> RGB); 3] = [
+            (h / 360., &|t| RGB::from_hsv((t * 360.).min(359.9), 1., 1.)),
+            (s, &|t| RGB::from_hsv(h.min(359.9), t, v)),
+            (v, &|t| RGB::from_hsv(h.min(359.9), s, t)),
+        ];
+        for (idx, (value, bar_color)) in bars.iter().enumerate() {
+            let top = Self::bar_top(idx);
+            for k in 0..columns {
+                let x = MARGIN + k as f64;
+                let rect = Rect::new(x, top, x + 1., top + BAR_HEIGHT);
+                ctx.fill(rect, &color(bar_color(k as f64 / width)));
+            }
+            let x = Self::pos_to_x(size, *value);
+            let knob = Rect::new(x - 2., top - 1., x + 2., top + BAR_HEIGHT + 1.);
+            ctx.stroke(knob, &Color::WHITE, 1.);
+        }
+    }
+}
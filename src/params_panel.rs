@@ -0,0 +1,237 @@
+use druid::text::ParseFormatter;
+use druid::widget::{
+    Button, Checkbox, Controller, CrossAxisAlignment, Flex, Label, RadioGroup, Scroll, Slider,
+    TextBox,
+};
+use druid::{
+    commands, lens, Data, Env, Event, EventCtx, FileDialogOptions, FileSpec, LensExt, Point,
+    Widget, WidgetExt,
+};
+
+use crate::delegate::UNSKEW;
+use crate::fractal::{
+    ColorMode, Formula, InteriorMode, IterPolicy, OrbitTrap, RenderParams, TrapShape,
+};
+use crate::fractal_widget::FractalData;
+use crate::palette::Palette;
+use crate::palette_editor::PaletteEditor;
+use crate::viewport::{Mat2, Viewport};
+
+const LABEL_WIDTH: f64 = 80.;
+
+/// Every variant of an enum, following `next` from `first`
+fn variants<T: Copy + PartialEq>(
+    first: T,
+    next: impl Fn(&T) -> T,
+    name: impl Fn(&T) -> &'static str,
+) -> Vec<(&'static str, T)> {
+    let mut variants = vec![(name(&first), first)];
+    let mut variant = next(&first);
+    while variant != first {
+        variants.push((name(&variant), variant));
+        variant = next(&variant);
+    }
+    variants
+}
+
+/// Labelled parameter row
+fn row<T: Data>(label: &str, widget: impl Widget<T> + 'static) -> impl Widget<T> {
+    Flex::row()
+        .with_child(Label::new(label).fix_width(LABEL_WIDTH))
+        .with_flex_child(widget, 1.)
+        .padding((0., 2.))
+}
+
+fn section<T: Data>(title: &str) -> impl Widget<T> {
+    Label::new(title)
+        .with_text_size(16.)
+        .padding((0., 8., 0., 2.))
+}
+
+fn slider(min: f64, max: f64) -> impl Widget<f64> {
+    Flex::row()
+        .with_flex_child(Slider::new().with_range(min, max).expand_width(), 1.)
+        .with_child(Label::new(|value: &f64, _: &_| format!("{:.2}", value)).fix_width(40.))
+}
+
+/// Records the view in the history once a view control is released
+struct PushHistoryOnRelease;
+
+impl<W: Widget<FractalData>> Controller<FractalData, W> for PushHistoryOnRelease {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut FractalData,
+        env: &Env,
+    ) {
+        child.event(ctx, event, data, env);
+        if let Event::MouseUp(_) = event {
+            data.push_history();
+        }
+    }
+}
+
+fn number_box() -> impl Widget<f64> {
+    TextBox::new().with_formatter(ParseFormatter::new())
+}
+
+pub fn build_panel() -> impl Widget<FractalData> {
+    let julia_c = Flex::row()
+        .with_flex_child(number_box().lens(lens!(Point, x)), 1.)
+        .with_flex_child(number_box().lens(lens!(Point, y)), 1.);
+    let iterations = Flex::row()
+        .with_child(Checkbox::new("auto").lens(IterPolicy::auto))
+        .with_flex_child(
+            TextBox::new()
+                .with_formatter(ParseFormatter::<usize>::new())
+                .lens(IterPolicy::count),
+            1.,
+        );
+    let trap = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::column(variants(TrapShape::Point, TrapShape::next, TrapShape::name))
+                .lens(OrbitTrap::shape),
+        )
+        .with_child(row("radius", slider(0.01, 2.).lens(OrbitTrap::radius)))
+        .with_child(row("angle", slider(0., 180.).lens(OrbitTrap::angle)));
+
+    // Through `set_formula`, which resets the view like the other formula switches
+    let formula_lens = lens::Map::new(
+        |data: &FractalData| data.params.formula,
+        |data: &mut FractalData, formula| {
+            if formula != data.params.formula {
+                data.set_formula(formula);
+            }
+        },
+    );
+    let formula = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(section("Formula"))
+        .with_child(
+            RadioGroup::column(variants(Formula::Mandelbrot, Formula::next, Formula::name))
+                .lens(formula_lens),
+        );
+
+    let params = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(row("julia c", julia_c.lens(RenderParams::julia_c)))
+        .with_child(row("iterations", iterations.lens(RenderParams::iterations)))
+        .with_child(row(
+            "escape radius",
+            slider(2., 100.).lens(RenderParams::escape_radius),
+        ))
+        .with_child(section("Coloring"))
+        .with_child(
+            RadioGroup::column(variants(
+                ColorMode::Smooth,
+                ColorMode::next,
+                ColorMode::name,
+            ))
+            .lens(RenderParams::color_mode),
+        )
+        .with_child(
+            PaletteEditor::new()
+                .lens(RenderParams::palette)
+                .padding((0., 4.)),
+        )
+        .with_child(row(
+            "palette offset",
+            slider(0., 1.).lens(RenderParams::palette.then(Palette::offset)),
+        ))
+        .with_child(row(
+            "stripes",
+            slider(1., 20.).lens(RenderParams::stripe_density),
+        ))
+        .with_child(section("Interior"))
+        .with_child(
+            RadioGroup::column(variants(
+                InteriorMode::Black,
+                InteriorMode::next,
+                InteriorMode::name,
+            ))
+            .lens(RenderParams::interior),
+        )
+        .with_child(section("Orbit trap"))
+        .with_child(trap.lens(RenderParams::trap))
+        .with_child(section("Shading"))
+        .with_child(Checkbox::new("enabled").lens(RenderParams::shading))
+        .with_child(row(
+            "light angle",
+            slider(0., 360.).lens(RenderParams::light_angle),
+        ))
+        .with_child(row(
+            "elevation",
+            slider(0., 90.).lens(RenderParams::light_elevation),
+        ))
+        .with_child(row("height", slider(0.1, 5.).lens(RenderParams::height)))
+        .lens(FractalData::params);
+
+    let trap_image = Button::<FractalData>::new("trap image").on_click(|ctx, _data, _env| {
+        let ppm = FileSpec::new("PPM image", &["ppm"]);
+        let options = FileDialogOptions::new().allowed_types(vec![ppm]);
+        ctx.submit_command(commands::SHOW_OPEN_PANEL.with(options));
+    });
+
+    let skew = Flex::column()
+        .with_child(
+            Flex::row()
+                .with_flex_child(number_box().lens(lens!(Mat2, a)), 1.)
+                .with_flex_child(number_box().lens(lens!(Mat2, b)), 1.),
+        )
+        .with_child(
+            Flex::row()
+                .with_flex_child(number_box().lens(lens!(Mat2, c)), 1.)
+                .with_flex_child(number_box().lens(lens!(Mat2, d)), 1.),
+        );
+    // Singular edits are dropped, the view would have no inverse
+    let skew_lens = lens::Map::new(
+        |data: &FractalData| data.view().skew,
+        |data: &mut FractalData, skew: Mat2| {
+            if skew.is_invertible() {
+                data.set_skew(skew);
+            }
+        },
+    );
+    let view = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(section("View"))
+        .with_child(row(
+            "rotation",
+            slider(0., 360.)
+                .lens(FractalData::view_lens.then(Viewport::rotation))
+                .controller(PushHistoryOnRelease),
+        ))
+        .with_child(row("skew", skew.lens(skew_lens)))
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("unskew")
+                        .on_click(|ctx, _data: &mut FractalData, _env| ctx.submit_command(UNSKEW)),
+                )
+                .with_child(
+                    Button::new("reset skew")
+                        .on_click(|_ctx, data: &mut FractalData, _env| data.reset_skew()),
+                ),
+        );
+
+    let cycling = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(section("Palette cycling"))
+        .with_child(Checkbox::new("enabled").lens(FractalData::cycle_palette))
+        .with_child(row("speed", slider(-1., 1.).lens(FractalData::cycle_speed)));
+
+    Scroll::new(
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(formula)
+            .with_child(params)
+            .with_child(view)
+            .with_child(cycling)
+            .with_child(trap_image)
+            .padding(5.),
+    )
+    .vertical()
+}
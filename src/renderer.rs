@@ -1,19 +1,23 @@
 use crate::fractal::*;
 use crate::image_utils::{IPoint, RGB};
-use crate::time::Instant;
+use crate::time::{Duration, Instant};
+use crate::viewport::Viewport;
 use druid::kurbo::Circle;
-use druid::{Point, Size};
+use druid::{Data, Point, Size};
 use num_cpus;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
 const STAGES: usize = 4;
 const BATCH: usize = 100;
+// Batches recolored before computing a new one, recoloring is much cheaper
+const RECOLOR_BATCHES: usize = 8;
 
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone)]
 struct Input {
     size: Size,
-    focus: Circle,
+    view: Viewport,
+    params: RenderParams,
     stage: usize,
     quit: bool,
 }
@@ -22,28 +26,69 @@ impl Input {
     fn new() -> Self {
         Input {
             size: Size::ZERO,
-            focus: Circle::new(Point::ZERO, 0.),
+            view: Viewport::new(Circle::new(Point::ZERO, 0.)),
+            params: RenderParams::new(),
             stage: 0,
             quit: false,
         }
     }
 
-    fn resize(size: Size, focus: Circle) -> Self {
+    fn resize(size: Size, view: Viewport, params: RenderParams) -> Self {
         Input {
             size: size,
-            focus: focus,
+            view: view,
+            params: params,
             stage: 0,
             quit: false,
         }
     }
 }
 
+/// Work done by a thread in a stage
+#[derive(Clone, Copy, Default)]
+struct StageStats {
+    pixels: usize,
+    iterations: u64,
+    escaped: usize,
+    // Time spent computing batches
+    busy: Duration,
+}
+
+impl StageStats {
+    fn merge(&mut self, other: &StageStats) {
+        self.pixels += other.pixels;
+        self.iterations += other.iterations;
+        self.escaped += other.escaped;
+        self.busy += other.busy;
+    }
+}
+
 struct ThreadData {
     id: usize,
     batch_idx: usize,
     complete: bool,
     input: Input,
     buffers: [Vec<RGB>; STAGES],
+    // Iteration results of the current stage, kept for recoloring if enabled
+    keep_results: bool,
+    results: Vec<ColorData>,
+    // Batches of the current stage left to recolor, from `recolor_idx`
+    recolor_idx: usize,
+    recolor_end: usize,
+    // Coloring changed during the running pass, start another one after it
+    recolor_again: bool,
+    // Work done in the current stage
+    stats: StageStats,
+}
+
+impl ThreadData {
+    // Nothing to compute or recolor until the input changes
+    fn idle(&self) -> bool {
+        self.complete
+            && !self.input.quit
+            && self.recolor_idx >= self.recolor_end
+            && !self.recolor_again
+    }
 }
 
 struct RendererThread {
@@ -51,10 +96,12 @@ struct RendererThread {
     thread_count: usize,
     thread: Option<thread::JoinHandle<()>>,
     data: Arc<Mutex<ThreadData>>,
+    // Wakes the idle thread on new input
+    wake: Arc<Condvar>,
 }
 
 impl RendererThread {
-    fn new(id: usize, thread_count: usize) -> Self {
+    fn new(id: usize, thread_count: usize, keep_results: bool) -> Self {
         let buf: [Vec<RGB>; STAGES] = Default::default();
         let data = Arc::new(Mutex::new(ThreadData {
             id: id,
@@ -62,11 +109,18 @@ impl RendererThread {
             complete: false,
             input: Input::new(),
             buffers: buf,
+            keep_results: keep_results,
+            results: Vec::new(),
+            recolor_idx: 0,
+            recolor_end: 0,
+            recolor_again: false,
+            stats: StageStats::default(),
         }));
         RendererThread {
             thread_count: thread_count,
             thread: None,
             data: data,
+            wake: Arc::new(Condvar::new()),
         }
     }
 
@@ -88,18 +142,57 @@ impl RendererThread {
 
     fn init_buffer(data: &mut ThreadData, thread_count: usize) {
         let size = Self::buffer_length(data, thread_count);
-        data.buffers[data.input.stage].resize(size, RGB::TRANSPARENT);
-        data.buffers[data.input.stage].fill(RGB::TRANSPARENT);
+        let stage = data.input.stage;
+        data.buffers[stage].resize(size, RGB::TRANSPARENT);
+        data.buffers[stage].fill(RGB::TRANSPARENT);
+        if data.keep_results {
+            data.results.resize(size, ColorData::default());
+        }
+        data.recolor_idx = 0;
+        data.recolor_end = 0;
+        data.recolor_again = false;
+        data.stats = StageStats::default();
+    }
+
+    /// Recolor one batch computed with a previous coloring, if any
+    fn recolor_batch(data: &mut ThreadData) -> bool {
+        if data.recolor_idx >= data.recolor_end {
+            if !data.recolor_again {
+                return false;
+            }
+            // Pass done, start over with the newest coloring
+            data.recolor_again = false;
+            data.recolor_idx = 0;
+            data.recolor_end = data.batch_idx;
+            if data.recolor_end == 0 {
+                return false;
+            }
+        }
+        let stage = data.input.stage;
+        let start = data.recolor_idx * BATCH;
+        let params = &data.input.params;
+        let results = &data.results[start..start + BATCH];
+        let buffer = &mut data.buffers[stage][start..start + BATCH];
+        for (rgb, res) in buffer.iter_mut().zip(results.iter()) {
+            *rgb = color_scheme(res, params);
+        }
+        data.recolor_idx += 1;
+        true
     }
 
     fn start(&mut self) {
         let thread_count = self.thread_count;
         if thread_count > 1 {
             let data = Arc::clone(&self.data);
+            let wake = Arc::clone(&self.wake);
             let thread = thread::spawn(move || loop {
                 if Self::thread_loop(&data, thread_count) {
                     break;
                 }
+                let mut data = data.lock().unwrap();
+                while data.idle() {
+                    data = wake.wait(data).unwrap();
+                }
             });
             self.thread = Some(thread);
         }
@@ -113,12 +206,19 @@ impl RendererThread {
         let input: Input;
         let mut complete: bool;
         {
-            let data = data.lock().unwrap();
+            let mut data = data.lock().unwrap();
             if data.input.quit {
                 return true;
             }
+            // Recolor a few batches, then compute a new one so that
+            // rendering keeps progressing during long recolorings
+            for _ in 0..RECOLOR_BATCHES {
+                if !Self::recolor_batch(&mut data) {
+                    break;
+                }
+            }
             (batch_idx, id, complete) = (data.batch_idx, data.id, data.complete);
-            input = data.input;
+            input = data.input.clone();
             size = Self::current_size(&data);
         }
         // Skip if complete
@@ -129,28 +229,41 @@ impl RendererThread {
         let idx = (thread_count * batch_idx + id) * BATCH;
         complete = idx >= size.x * size.y;
         let mut buf = [RGB::TRANSPARENT; BATCH];
+        let mut results = [ColorData::default(); BATCH];
+        let mut stats = StageStats::default();
+        let batch_start = Instant::now();
         if !complete {
             // Process buffer
             for k in 0..BATCH {
                 let idx = idx + k;
                 let (x, y) = (idx % size.x, idx / size.x);
 
-                // TODO: tune
-                let f = (0.001 + 2.0 * input.focus.radius).sqrt();
-                let max_iter = (223.0 / f).floor() as usize;
-
-                let size = Size::new(size.x as f64, size.y as f64);
-                let world = px_to_world(&input.focus, &size, &IPoint { x: x, y: y });
+                let px_size = Size::new(size.x as f64, size.y as f64);
+                // World point, world size of a pixel and view radius
+                let (world, pixel_size, radius) =
+                    input
+                        .params
+                        .projection
+                        .map(&input.view, &px_size, &IPoint { x: x, y: y });
+                let max_iter = input.params.iterations.max_iter(radius);
                 let res = mandelbrot(
                     Complex {
                         r: world.x,
                         i: world.y,
                     },
-                    100.,
+                    input.params.escape_radius * input.params.escape_radius,
                     max_iter,
+                    &input.params,
                 );
-                let rgb = color_scheme(&res);
-                buf[k] = rgb;
+                let color = ColorData::new(&res, pixel_size, input.params.color_mode);
+                buf[k] = color_scheme(&color, &input.params);
+                results[k] = color;
+                // The last batch runs past the image
+                if idx < size.x * size.y {
+                    stats.pixels += 1;
+                    stats.iterations += res.iter() as u64;
+                    stats.escaped += res.escaped() as usize;
+                }
 
                 // Benchmark
                 // thread::sleep(Duration::from_micros(4 * (4 - input.stage as u64).pow(2)));
@@ -161,17 +274,35 @@ impl RendererThread {
                 //     buf[k] = RGB::rand();
                 // }
             }
+            stats.busy = batch_start.elapsed();
         }
         // Now append that batch
         {
             let mut data = data.lock().unwrap();
+            // Drop the work of a previous input, unless only the coloring changed
+            let recolorable = data.keep_results && data.input.params.same_orbits(&input.params);
+            let same_input = data.batch_idx == batch_idx
+                && data.input.stage == input.stage
+                && data.input.size == input.size
+                && data.input.view == input.view
+                && (data.input.params == input.params || recolorable);
+            if !same_input {
+                return false;
+            }
             data.complete = complete;
-            if !complete && Self::current_size(&data) == size {
+            if !complete {
                 let stage = data.input.stage;
-                let buffer = &mut data.buffers[stage];
-                let slice = &mut buffer[batch_idx * BATCH..(batch_idx + 1) * BATCH];
-                slice.copy_from_slice(&buf);
+                let range = batch_idx * BATCH..(batch_idx + 1) * BATCH;
+                data.buffers[stage][range.clone()].copy_from_slice(&buf);
+                if data.keep_results {
+                    data.results[range].copy_from_slice(&results);
+                }
                 data.batch_idx += 1;
+                data.stats.merge(&stats);
+                // Colored with parameters changed since, recolor it
+                if data.input.params != input.params {
+                    data.recolor_end = data.batch_idx;
+                }
             }
         }
         false
@@ -179,20 +310,37 @@ impl RendererThread {
 
     fn stop(&mut self) {
         self.data.lock().unwrap().input.quit = true;
+        self.wake.notify_one();
         self.thread.take().map(|thread| thread.join());
     }
 
-    fn resize(&self, size: Size, focus: Circle) {
-        self.data.lock().unwrap().input = Input::resize(size, focus);
+    fn resize(&self, size: Size, view: Viewport, params: RenderParams) {
+        self.data.lock().unwrap().input = Input::resize(size, view, params);
         self.set_stage(0);
     }
 
+    /// Switch to parameters with the same orbits, recoloring the stored results
+    fn recolor(&self, params: RenderParams) {
+        let mut data = self.data.lock().unwrap();
+        data.input.params = params;
+        if data.recolor_idx < data.recolor_end {
+            // Let the running pass finish, restarting it on every change
+            // would keep the last batches from ever catching up
+            data.recolor_again = true;
+        } else {
+            data.recolor_idx = 0;
+            data.recolor_end = data.batch_idx;
+        }
+        self.wake.notify_one();
+    }
+
     fn set_stage(&self, stage: usize) {
         let mut data = self.data.lock().unwrap();
         data.input.stage = stage;
         data.complete = false;
         data.batch_idx = 0;
         Self::init_buffer(&mut data, self.thread_count);
+        self.wake.notify_one();
     }
 
     fn status(&self, stage: usize) -> (bool, f64) {
@@ -213,6 +361,15 @@ impl RendererThread {
         (data.input.stage == stage && data.complete, progress)
     }
 
+    fn stats(&self) -> StageStats {
+        self.data.lock().unwrap().stats
+    }
+
+    fn is_recoloring(&self) -> bool {
+        let data = self.data.lock().unwrap();
+        data.recolor_idx < data.recolor_end || data.recolor_again
+    }
+
     fn populate_image(&self, image: &mut [RGB]) {
         // Run a fixed number of loops if thread hasn't started
         if self.thread.is_none() {
@@ -257,33 +414,135 @@ impl RendererThread {
 
 pub struct RendererResult {
     pub image_size: IPoint,
+    /// Stage the image belongs to
+    pub stage: usize,
     pub progress: f64,
+    /// Whether the final stage is rendered
+    pub complete: bool,
+}
+
+/// Timing and throughput of the current render
+#[derive(Clone, Default, PartialEq)]
+pub struct RenderStats {
+    /// Duration of each completed stage
+    pub stage_times: [Option<Duration>; STAGES],
+    pub elapsed: Duration,
+    /// Whether the final stage is complete
+    pub done: bool,
+    /// Estimated time left until the final stage completes, unknown until
+    /// a batch is done
+    pub eta: Option<Duration>,
+    pub pixels: usize,
+    pub iterations: u64,
+    pub threads: usize,
+    /// Fraction of the threads' time spent computing
+    pub utilization: f64,
+    /// Escaped and interior pixels of the current stage
+    pub escaped: usize,
+    pub interior: usize,
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{} ms", duration.as_millis())
+    } else {
+        format!("{:.2} s", duration.as_secs_f64())
+    }
+}
+
+impl Data for RenderStats {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl RenderStats {
+    pub fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for (stage, time) in self.stage_times.iter().enumerate() {
+            let time = time.map(format_duration).unwrap_or("-".to_string());
+            lines.push(format!("stage {}: {}", stage + 1, time));
+        }
+        let eta = match self.eta {
+            _ if self.done => "done".to_string(),
+            Some(eta) => format!("eta {}", format_duration(eta)),
+            None => "eta —".to_string(),
+        };
+        lines.push(format!(
+            "elapsed {}, {}",
+            format_duration(self.elapsed),
+            eta
+        ));
+        let secs = self.elapsed.as_secs_f64().max(1e-3);
+        lines.push(format!(
+            "{:.1} Mpx/s, {:.1} Mit/s",
+            self.pixels as f64 / secs * 1e-6,
+            self.iterations as f64 / secs * 1e-6
+        ));
+        lines.push(format!(
+            "{} threads, {:.0}% busy",
+            self.threads,
+            self.utilization * 100.
+        ));
+        lines.push(format!(
+            "escaped {}, interior {}",
+            self.escaped, self.interior
+        ));
+        lines.join("\n")
+    }
 }
 
 pub struct Renderer {
     stage: usize,
     size: Size,
-    focus: Circle,
+    view: Viewport,
+    params: RenderParams,
     threads: Vec<RendererThread>,
+    // Whether coloring changes recolor the stored results
+    recolor: bool,
+    progress: f64,
+    // Timing of the current render
+    start: Instant,
+    stage_start: Instant,
+    stage_times: [Option<Duration>; STAGES],
+    // Work of the completed stages
+    done: StageStats,
 }
 
 impl Renderer {
     pub fn new() -> Self {
+        Self::create(false)
+    }
+
+    /// Renderer keeping the iteration results of the current stage, about
+    /// 56 bytes per pixel, so that coloring changes don't iterate again
+    pub fn with_recoloring() -> Self {
+        Self::create(true)
+    }
+
+    fn create(recolor: bool) -> Self {
         let thread_count = num_cpus::get();
         println!("Starting {} threads", thread_count);
 
         // Create threads
         let mut threads: Vec<RendererThread> = Vec::new();
         for id in 0..thread_count {
-            let mut thread = RendererThread::new(id, thread_count);
+            let mut thread = RendererThread::new(id, thread_count, recolor);
             thread.start();
             threads.push(thread);
         }
         Renderer {
             stage: 0,
             threads: threads,
-            focus: Circle::new(Point::ZERO, 0.),
+            recolor: recolor,
+            view: Viewport::new(Circle::new(Point::ZERO, 0.)),
+            params: RenderParams::new(),
             size: Size::ZERO,
+            progress: 0.,
+            start: Instant::now(),
+            stage_start: Instant::now(),
+            stage_times: Default::default(),
+            done: StageStats::default(),
         }
     }
 
@@ -293,14 +552,31 @@ impl Renderer {
         }
     }
 
-    pub fn resize(&mut self, size: Size, focus: Circle) {
-        if size != self.size || focus != self.focus {
+    pub fn resize(&mut self, size: Size, view: Viewport, params: &RenderParams) {
+        let same_view = size == self.size && view == self.view;
+        if self.recolor && same_view && *params != self.params {
+            if params.same_orbits(&self.params) {
+                // Only the coloring changed
+                self.params = params.clone();
+                for thread in self.threads.iter_mut() {
+                    thread.recolor(params.clone());
+                }
+                return;
+            }
+        }
+        if !same_view || *params != self.params {
             self.stage = 0;
             self.size = size;
-            self.focus = focus;
+            self.view = view;
+            self.params = params.clone();
             for thread in self.threads.iter_mut() {
-                thread.resize(size, focus);
+                thread.resize(size, view, params.clone());
             }
+            self.progress = 0.;
+            self.start = Instant::now();
+            self.stage_start = self.start;
+            self.stage_times = Default::default();
+            self.done = StageStats::default();
         }
     }
 
@@ -328,9 +604,17 @@ impl Renderer {
             // Rendering done!
             mean_progress = 1.;
         }
+        self.progress = mean_progress;
+        // Record the stage time once complete
+        if complete_count == thread_count && self.stage_times[self.stage].is_none() {
+            self.stage_times[self.stage] = Some(self.stage_start.elapsed());
+        }
         // Advance the stage if needed
         if self.stage < STAGES - 1 {
             if complete_count == thread_count {
+                let stats = self.thread_stats();
+                self.done.merge(&stats);
+                self.stage_start = Instant::now();
                 self.stage += 1;
                 for thread in self.threads.iter_mut() {
                     thread.set_stage(self.stage);
@@ -345,7 +629,76 @@ impl Renderer {
 
         RendererResult {
             image_size: size,
+            stage: self.stage,
             progress: mean_progress,
+            complete: self.stage == STAGES - 1 && complete_count == thread_count,
+        }
+    }
+
+    fn thread_stats(&self) -> StageStats {
+        let mut stats = StageStats::default();
+        for thread in self.threads.iter() {
+            stats.merge(&thread.stats());
+        }
+        stats
+    }
+
+    /// Whether stored results are still being recolored
+    pub fn is_recoloring(&self) -> bool {
+        self.threads.iter().any(|thread| thread.is_recoloring())
+    }
+
+    /// Statistics of the current render
+    pub fn stats(&self) -> RenderStats {
+        let current = self.thread_stats();
+        let mut total = self.done;
+        total.merge(&current);
+        // Stop the clock once the final stage is complete
+        let done = self.stage_times[STAGES - 1].is_some();
+        let (elapsed, eta) = if done {
+            (self.stage_times.iter().flatten().sum(), None)
+        } else {
+            let elapsed = self.start.elapsed();
+            let eta = if self.progress > 0. {
+                Some(elapsed.mul_f64((1. - self.progress) / self.progress))
+            } else {
+                None
+            };
+            (elapsed, eta)
+        };
+        let thread_time = elapsed.as_secs_f64() * self.threads.len() as f64;
+        RenderStats {
+            stage_times: self.stage_times,
+            elapsed: elapsed,
+            done: done,
+            eta: eta,
+            pixels: total.pixels,
+            iterations: total.iterations,
+            threads: self.threads.len(),
+            utilization: (total.busy.as_secs_f64() / thread_time.max(1e-9)).min(1.),
+            escaped: current.escaped,
+            interior: current.pixels - current.escaped,
         }
     }
+
+    /// Render the final stage, blocking until complete
+    pub fn render(&mut self, size: Size, view: Viewport, params: &RenderParams) -> Vec<RGB> {
+        let mut image = Vec::new();
+        self.resize(size, view, params);
+        loop {
+            if self.update(&mut image).complete {
+                // Populate the batches completed since the last update
+                self.update(&mut image);
+                return image;
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        self.stop();
+    }
 }
@@ -12,11 +12,17 @@ pub enum StackAlign {
     TopRight,
     BottomLeft,
     BottomRight,
+    /// Next to the mouse cursor, kept inside the stack
+    Cursor,
 }
 
+// Offset of the cursor aligned widgets from the cursor
+const CURSOR_OFFSET: f64 = 16.;
+
 pub struct StackWidget<T> {
     widgets: Vec<WidgetPod<T, Box<dyn Widget<T>>>>,
     aligns: Vec<StackAlign>,
+    cursor: Point,
 }
 
 impl<T> StackWidget<T> {
@@ -24,6 +30,7 @@ impl<T> StackWidget<T> {
         StackWidget {
             widgets: vec![],
             aligns: vec![],
+            cursor: Point::ZERO,
         }
     }
 
@@ -42,7 +49,37 @@ impl<T> Default for StackWidget<T> {
 
 impl<T: Data> Widget<T> for StackWidget<T> {
     fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut T, env: &Env) {
-        for widget in self.widgets.iter_mut() {
+        if let Event::MouseMove(mouse) = event {
+            self.cursor = mouse.pos;
+            if self
+                .aligns
+                .iter()
+                .any(|align| matches!(align, StackAlign::Cursor))
+            {
+                ctx.request_layout();
+            }
+        }
+        // Every widget tracks the cursor to keep its hot state current. The ones
+        // covered by an overlay see it leave, unless dragging.
+        if let Event::MouseMove(mouse) = event {
+            let mut covered = false;
+            for widget in self.widgets.iter_mut().rev() {
+                if covered && !widget.has_active() {
+                    let mut outside = mouse.clone();
+                    outside.pos = Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY);
+                    widget.event(ctx, &Event::MouseMove(outside), data, env);
+                } else {
+                    widget.event(ctx, event, data, env);
+                }
+                covered |= widget.layout_rect().contains(mouse.pos);
+            }
+            return;
+        }
+        // Topmost widgets first, they may handle the event
+        for widget in self.widgets.iter_mut().rev() {
+            if ctx.is_handled() {
+                break;
+            }
             widget.event(ctx, event, data, env);
         }
     }
@@ -62,9 +99,14 @@ impl<T: Data> Widget<T> for StackWidget<T> {
     fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &T, env: &Env) -> Size {
         let mut sizes: Vec<Size> = vec![];
         let mut max_size: Size = Size::ZERO;
-        for widget in self.widgets.iter_mut() {
+        for (idx, widget) in self.widgets.iter_mut().enumerate() {
             //  &BoxConstraints::new(self.cell_size, self.cell_size)
-            let size = widget.layout(ctx, bc, data, env);
+            // Overlays at the cursor keep their own size
+            let child_bc = match self.aligns[idx] {
+                StackAlign::Cursor => bc.loosen(),
+                _ => *bc,
+            };
+            let size = widget.layout(ctx, &child_bc, data, env);
             sizes.push(size);
             max_size.width = max_size.width.max(size.width);
             max_size.height = max_size.height.max(size.height);
@@ -89,6 +131,18 @@ impl<T: Data> Widget<T> for StackWidget<T> {
                 StackAlign::BottomRight => {
                     (max_size.width - size.width, max_size.height - size.height)
                 }
+                StackAlign::Cursor => {
+                    // Below right of the cursor, flipped when it would overflow
+                    let mut x = self.cursor.x + CURSOR_OFFSET;
+                    if x + size.width > max_size.width {
+                        x = self.cursor.x - CURSOR_OFFSET - size.width;
+                    }
+                    let mut y = self.cursor.y + CURSOR_OFFSET;
+                    if y + size.height > max_size.height {
+                        y = self.cursor.y - CURSOR_OFFSET - size.height;
+                    }
+                    (x.max(0.), y.max(0.))
+                }
             };
             widget.set_origin(ctx, data, env, Point::new(x, y));
         }
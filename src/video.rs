@@ -0,0 +1,410 @@
+use crate::fractal::{IterPolicy, Projection, RenderParams};
+use crate::image_utils::{IPoint, Texture, RGB};
+use crate::location::Location;
+use crate::renderer::Renderer;
+use crate::viewport::Viewport;
+use druid::kurbo::Circle;
+use druid::Size;
+use std::f64::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str =
+    "usage: fractal video <keyframes> <output dir> [--size WxH] [--frames FIRST-LAST] [--exp-map]";
+// Exponential map strip, cached in the output directory
+const STRIP_NAME: &str = "exp_map.ppm";
+// Location and parameters the cached strip was rendered with
+const STRIP_KEY_NAME: &str = "exp_map.fractal";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "ease_in" => Some(Easing::EaseIn),
+            "ease_out" => Some(Easing::EaseOut),
+            "ease_in_out" => Some(Easing::EaseInOut),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2. - t),
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+        }
+    }
+}
+
+/// Location reached at a given frame, and the easing towards the next keyframe
+pub struct Keyframe {
+    pub frame: usize,
+    pub location: Location,
+    pub easing: Easing,
+}
+
+/// Parse `[frame]` sections of location text, with an optional `easing` key
+pub fn parse_keyframes(text: &str) -> Result<Vec<Keyframe>, String> {
+    let mut sections: Vec<(String, String)> = Vec::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            sections.push((trimmed[1..trimmed.len() - 1].to_string(), String::new()));
+        } else if let Some((_, body)) = sections.last_mut() {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+    let mut keyframes = Vec::new();
+    for (header, body) in sections {
+        let frame = header
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid keyframe '{}'", header))?;
+        let location =
+            Location::from_text(&body).map_err(|err| format!("Keyframe {}: {}", frame, err))?;
+        let mut easing = Easing::Linear;
+        for line in body.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "easing" {
+                    easing = Easing::from_name(value.trim())
+                        .ok_or_else(|| format!("Unknown easing '{}'", value.trim()))?;
+                }
+            }
+        }
+        keyframes.push(Keyframe {
+            frame: frame,
+            location: location,
+            easing: easing,
+        });
+    }
+    keyframes.sort_by_key(|keyframe| keyframe.frame);
+    if keyframes.is_empty() {
+        return Err("No keyframes".to_string());
+    }
+    Ok(keyframes)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// View and parameters of a frame, interpolated between the surrounding keyframes
+pub fn frame_at(keyframes: &[Keyframe], frame: usize) -> (Viewport, RenderParams) {
+    let next = keyframes
+        .iter()
+        .position(|keyframe| keyframe.frame > frame)
+        .unwrap_or(keyframes.len() - 1);
+    let k0 = &keyframes[next.saturating_sub(1)];
+    let k1 = &keyframes[next];
+    let (l0, l1) = (&k0.location, &k1.location);
+    let t = if k1.frame > k0.frame {
+        let t = (frame.clamp(k0.frame, k1.frame) - k0.frame) as f64 / (k1.frame - k0.frame) as f64;
+        k0.easing.apply(t)
+    } else {
+        0.
+    };
+
+    // Constant zoom speed
+    let radius = lerp(l0.radius.ln(), l1.radius.ln(), t).exp();
+    // Approach the next center in proportion to the zoom, so that it
+    // stays in view instead of drifting away during deep zooms
+    let s = if (l0.radius - l1.radius).abs() > f64::EPSILON * l0.radius {
+        (radius - l1.radius) / (l0.radius - l1.radius)
+    } else {
+        1. - t
+    };
+    let center = l1.center + (l0.center - l1.center) * s;
+    let view = Viewport {
+        focus: Circle::new(center, radius),
+        rotation: lerp(l0.rotation, l1.rotation, t),
+        skew: l0.skew.lerp(&l1.skew, t),
+    };
+
+    let mut params = RenderParams::new();
+    l0.apply(&mut params);
+    params.iterations.count =
+        lerp(l0.iterations.count as f64, l1.iterations.count as f64, t).round() as usize;
+    params.palette.offset = lerp(l0.palette.offset, l1.palette.offset, t);
+    (view, params)
+}
+
+fn frame_path(dir: &Path, frame: usize) -> PathBuf {
+    dir.join(format!("frame_{:05}.ppm", frame))
+}
+
+/// Render the frames `first..=last`, skipping the ones already written
+pub fn render_frames(
+    keyframes: &[Keyframe],
+    size: IPoint,
+    dir: &Path,
+    first: usize,
+    last: usize,
+) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    let mut renderer = Renderer::new();
+    for frame in first..=last {
+        let path = frame_path(dir, frame);
+        if path.exists() {
+            continue;
+        }
+        let (view, params) = frame_at(keyframes, frame);
+        let frame_size = Size::new(size.x as f64, size.y as f64);
+        let pixels = renderer.render(frame_size, view, &params);
+        let texture = Texture {
+            size: size,
+            pixels: pixels,
+        };
+        write_frame(&path, &texture)?;
+        println!("Frame {} / {}", frame, last);
+    }
+    Ok(())
+}
+
+/// Write a frame, through a temporary file so that an interrupted write
+/// is never mistaken for a rendered frame on resume
+fn write_frame(path: &Path, texture: &Texture) -> Result<(), String> {
+    let tmp_path = path.with_extension("ppm.tmp");
+    fs::write(&tmp_path, texture.to_ppm()).map_err(|err| err.to_string())?;
+    fs::rename(&tmp_path, path).map_err(|err| err.to_string())
+}
+
+/// Bilinear sample of the strip at texel coordinates, wrapping around the angle
+fn sample_strip(strip: &Texture, x: f64, y: f64) -> RGB {
+    let (w, h) = (strip.size.x, strip.size.y);
+    let y = y.clamp(0., (h - 1) as f64);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let x0 = (x0 as i64).rem_euclid(w as i64) as usize;
+    let x1 = (x0 + 1) % w;
+    let y0 = y0 as usize;
+    let y1 = (y0 + 1).min(h - 1);
+    let texel = |x: usize, y: usize| strip.pixels[x + w * y];
+    let top = texel(x0, y0).interpolate(&texel(x1, y0), fx);
+    let bottom = texel(x0, y1).interpolate(&texel(x1, y1), fx);
+    top.interpolate(&bottom, fy)
+}
+
+/// Reconstruct the view of `radius` around the strip center, the strip
+/// starting at `outer_radius`. Inverse of `exp_map_to_world`.
+fn exp_map_frame(strip: &Texture, outer_radius: f64, size: IPoint, radius: f64) -> Vec<RGB> {
+    let step = 2. * PI / strip.size.x as f64;
+    let pixel_size = 2. * radius / size.x.min(size.y) as f64;
+    let mut pixels = Vec::with_capacity(size.x * size.y);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let dx = (x as f64 - size.x as f64 / 2.) * pixel_size;
+            let dy = (y as f64 - size.y as f64 / 2.) * pixel_size;
+            let angle = dy.atan2(dx).rem_euclid(2. * PI);
+            // Rows past the strip end clamp to the innermost row
+            let row = (outer_radius / dx.hypot(dy).max(f64::MIN_POSITIVE)).ln() / step;
+            pixels.push(sample_strip(strip, angle / step, row));
+        }
+    }
+    pixels
+}
+
+/// Render the frames `first..=last` from a single exponential map strip.
+/// Only the zoom is followed: frames are centered on the last keyframe, with
+/// no rotation or skew, and colors come from its parameters. The strip spans
+/// all the keyframes, so that any range of frames shares the cached strip.
+pub fn render_exp_map_frames(
+    keyframes: &[Keyframe],
+    size: IPoint,
+    dir: &Path,
+    first: usize,
+    last: usize,
+) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    let target = &keyframes
+        .last()
+        .ok_or_else(|| "No keyframes".to_string())?
+        .location;
+    // The log radius is monotonic between keyframes, its extremes are keyframes
+    let keyframe_radii = keyframes.iter().map(|keyframe| keyframe.location.radius);
+    let min_radius = keyframe_radii.clone().fold(f64::INFINITY, f64::min);
+    let max_radius = keyframe_radii.fold(0., f64::max);
+    let diagonal = (size.x as f64).hypot(size.y as f64);
+    // From the corners of the widest frame to the center pixel of the deepest
+    let outer_radius = max_radius * diagonal / size.x.min(size.y) as f64;
+    let inner_radius = min_radius / size.x.min(size.y) as f64;
+    // One strip pixel per frame pixel at the frame corners
+    let width = (PI * diagonal).ceil() as usize;
+    let height = ((outer_radius / inner_radius).ln() * width as f64 / (2. * PI)).ceil() as usize;
+    let strip_size = IPoint::new(width, height.max(1));
+
+    let mut params = RenderParams::new();
+    target.apply(&mut params);
+    let strip_view = Viewport::new(Circle::new(target.center, outer_radius));
+    // Auto iterations would grow ring by ring, use the count of the deepest frame throughout
+    params.iterations = IterPolicy {
+        auto: false,
+        count: params.iterations.max_iter(min_radius),
+    };
+    params.projection = Projection::ExpMap;
+
+    // Reuse the cached strip only if rendered from the same location and parameters
+    let strip_path = dir.join(STRIP_NAME);
+    let key_path = dir.join(STRIP_KEY_NAME);
+    let key = Location::new(&strip_view, &params).to_text();
+    let cached = fs::read_to_string(&key_path)
+        .ok()
+        .filter(|cached_key| *cached_key == key)
+        .and_then(|_| fs::read(&strip_path).ok())
+        .and_then(|bytes| Texture::from_ppm(&bytes))
+        .filter(|strip| strip.size == strip_size);
+    let strip = match cached {
+        Some(strip) => strip,
+        None => {
+            println!("Rendering {}x{} strip", strip_size.x, strip_size.y);
+            // Invalidate the key first, a new strip never pairs with an old key
+            if key_path.exists() {
+                fs::remove_file(&key_path).map_err(|err| err.to_string())?;
+            }
+            let size = Size::new(strip_size.x as f64, strip_size.y as f64);
+            let strip = Texture {
+                size: strip_size,
+                pixels: Renderer::new().render(size, strip_view, &params),
+            };
+            write_frame(&strip_path, &strip)?;
+            fs::write(&key_path, &key).map_err(|err| err.to_string())?;
+            strip
+        }
+    };
+
+    for frame in first..=last {
+        let path = frame_path(dir, frame);
+        if path.exists() {
+            continue;
+        }
+        let radius = frame_at(keyframes, frame).0.focus.radius;
+        let texture = Texture {
+            size: size,
+            pixels: exp_map_frame(&strip, outer_radius, size, radius),
+        };
+        write_frame(&path, &texture)?;
+        println!("Frame {} / {}", frame, last);
+    }
+    Ok(())
+}
+
+fn parse_pair(value: &str, separator: char) -> Option<(usize, usize)> {
+    let (a, b) = value.split_once(separator)?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+/// Command line entry point, `args` excluding the program and command names
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (keyframes_path, dir) = match args {
+        [keyframes, dir, ..] => (keyframes, dir),
+        _ => return Err(USAGE.to_string()),
+    };
+    let mut size = IPoint::new(1280, 720);
+    let mut frames = None;
+    let mut exp_map = false;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        if option == "--exp-map" {
+            exp_map = true;
+            continue;
+        }
+        let value = options.next().ok_or_else(|| USAGE.to_string())?;
+        match option.as_str() {
+            "--size" => {
+                let (w, h) = parse_pair(value, 'x')
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or_else(|| USAGE.to_string())?;
+                size = IPoint::new(w, h);
+            }
+            "--frames" => frames = Some(parse_pair(value, '-').ok_or_else(|| USAGE.to_string())?),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let text = fs::read_to_string(keyframes_path).map_err(|err| err.to_string())?;
+    let keyframes = parse_keyframes(&text)?;
+    let (first, last) = frames.unwrap_or((0, keyframes[keyframes.len() - 1].frame));
+    if exp_map {
+        render_exp_map_frames(&keyframes, size, Path::new(dir), first, last)
+    } else {
+        render_frames(&keyframes, size, Path::new(dir), first, last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use druid::Point;
+
+    const KEYFRAMES: &str = "\
+[100]
+re = -0.75
+im = 0.1
+radius = 0.002
+
+[0]
+re = 0
+im = 0
+radius = 2
+easing = ease_in_out
+";
+
+    #[test]
+    fn parse_sorted() {
+        let keyframes = parse_keyframes(KEYFRAMES).unwrap();
+        let frames: Vec<usize> = keyframes.iter().map(|keyframe| keyframe.frame).collect();
+        assert_eq!(frames, vec![0, 100]);
+        assert!(keyframes[0].easing == Easing::EaseInOut);
+        assert!(keyframes[1].easing == Easing::Linear);
+    }
+
+    #[test]
+    fn endpoints_match_keyframes() {
+        let keyframes = parse_keyframes(KEYFRAMES).unwrap();
+        for keyframe in keyframes.iter() {
+            let (view, _) = frame_at(&keyframes, keyframe.frame);
+            let location = &keyframe.location;
+            assert!((view.focus.radius - location.radius).abs() < 1e-12 * location.radius);
+            assert!(view.focus.center.distance(location.center) < 1e-12);
+        }
+        // Clamped past the last keyframe
+        let (view, _) = frame_at(&keyframes, 1000);
+        assert!((view.focus.radius - 0.002).abs() < 1e-15);
+    }
+
+    #[test]
+    fn geometric_radius() {
+        let text = KEYFRAMES.replace("easing = ease_in_out", "");
+        let keyframes = parse_keyframes(&text).unwrap();
+        let (view, _) = frame_at(&keyframes, 50);
+        let mean = (2_f64 * 0.002).sqrt();
+        assert!((view.focus.radius - mean).abs() < 1e-12);
+        // Halfway to the target in proportion to the zoom
+        let s = (mean - 0.002) / (2. - 0.002);
+        let center = Point::new(-0.75 * (1. - s), 0.1 * (1. - s));
+        assert!(view.focus.center.distance(center) < 1e-12);
+    }
+
+    #[test]
+    fn invalid_keyframes() {
+        let location = "re = 0\nim = 0\nradius = 1\n";
+        for text in [
+            String::new(),
+            location.to_string(),
+            format!("[first]\n{}", location),
+            format!("[-1]\n{}", location),
+            "[0]\nre = 0\nim = 0\n".to_string(),
+            format!("[0]\n{}easing = bounce\n", location),
+        ] {
+            assert!(parse_keyframes(&text).is_err(), "{}", text);
+        }
+    }
+}
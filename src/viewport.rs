@@ -0,0 +1,391 @@
+use std::ops::{Add, Mul};
+
+use druid::kurbo::{Affine, Circle, Rect, Vec2};
+use druid::{Data, Lens, Point, Size};
+
+/// Linear map of the plane, `x' = a x + b y` and `y' = c x + d y`
+#[derive(Clone, Copy, PartialEq, Data)]
+pub struct Mat2 {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+}
+
+impl Mat2 {
+    pub const IDENTITY: Mat2 = Mat2 {
+        a: 1.,
+        b: 0.,
+        c: 0.,
+        d: 1.,
+    };
+
+    pub const FLIP_Y: Mat2 = Mat2 {
+        a: 1.,
+        b: 0.,
+        c: 0.,
+        d: -1.,
+    };
+
+    pub const ZERO: Mat2 = Mat2 {
+        a: 0.,
+        b: 0.,
+        c: 0.,
+        d: 0.,
+    };
+
+    /// Rotation by `angle` degrees, clockwise on screen
+    pub fn rotation(angle: f64) -> Self {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Mat2 {
+            a: cos,
+            b: -sin,
+            c: sin,
+            d: cos,
+        }
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (self.a * x + self.b * y, self.c * x + self.d * y)
+    }
+
+    pub fn det(&self) -> f64 {
+        self.a * self.d - self.b * self.c
+    }
+
+    /// Whether the matrix is finite and has an inverse
+    pub fn is_invertible(&self) -> bool {
+        let det = self.det();
+        det != 0. && det.is_finite()
+    }
+
+    pub fn scale(&self, k: f64) -> Self {
+        Mat2 {
+            a: self.a * k,
+            b: self.b * k,
+            c: self.c * k,
+            d: self.d * k,
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        Mat2 {
+            a: self.a,
+            b: self.c,
+            c: self.b,
+            d: self.d,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Mat2 {
+            a: self.d,
+            b: -self.b,
+            c: -self.c,
+            d: self.a,
+        }
+        .scale(1. / self.det())
+    }
+
+    /// Interpolation of the polar factors: the rotation turns the short way
+    /// and the stretch is blended linearly, so that the result stays
+    /// invertible. A reflection at one end only flips halfway.
+    pub fn lerp(&self, other: &Mat2, t: f64) -> Self {
+        let ((r0, s0), (r1, s1)) = (self.polar(), other.polar());
+        // Rotation angle, the reflection being applied first
+        let angle = |r: &Mat2| r.c.atan2(r.a).to_degrees();
+        let reflection = |r: &Mat2| {
+            if r.det() < 0. {
+                Mat2::FLIP_Y
+            } else {
+                Mat2::IDENTITY
+            }
+        };
+        let (a0, a1) = (angle(&r0), angle(&r1));
+        let turn = (a1 - a0 + 180.).rem_euclid(360.) - 180.;
+        let flip = if t < 0.5 {
+            reflection(&r0)
+        } else {
+            reflection(&r1)
+        };
+        Mat2::rotation(a0 + turn * t) * flip * (s0.scale(1. - t) + s1.scale(t))
+    }
+
+    /// Polar decomposition `self = rotation * stretch`, the stretch being
+    /// symmetric. A reflection, if any, is kept in the rotation.
+    pub fn polar(&self) -> (Mat2, Mat2) {
+        let s = if self.det() < 0. { -1. } else { 1. };
+        let (x, y) = (self.a + s * self.d, self.c - s * self.b);
+        let norm = x.hypot(y);
+        if norm == 0. {
+            return (Mat2::IDENTITY, *self);
+        }
+        let rotation = Mat2 {
+            a: x / norm,
+            b: -s * y / norm,
+            c: y / norm,
+            d: s * x / norm,
+        };
+        (rotation, rotation.transpose() * *self)
+    }
+}
+
+impl Add for Mat2 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Mat2 {
+            a: self.a + other.a,
+            b: self.b + other.b,
+            c: self.c + other.c,
+            d: self.d + other.d,
+        }
+    }
+}
+
+impl Mul for Mat2 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Mat2 {
+            a: self.a * other.a + self.b * other.c,
+            b: self.a * other.b + self.b * other.d,
+            c: self.c * other.a + self.d * other.c,
+            d: self.c * other.b + self.d * other.d,
+        }
+    }
+}
+
+/// A view of the complex plane: the focus circle fits the shorter side of
+/// the screen, turned by `rotation` then stretched by `skew`
+#[derive(Clone, Copy, PartialEq, Data, Lens)]
+pub struct Viewport {
+    pub focus: Circle,
+    /// Rotation, in degrees clockwise on screen
+    pub rotation: f64,
+    /// World side stretch, applied after the rotation
+    pub skew: Mat2,
+}
+
+impl Viewport {
+    pub fn new(focus: Circle) -> Self {
+        Viewport {
+            focus: focus,
+            rotation: 0.,
+            skew: Mat2::IDENTITY,
+        }
+    }
+
+    /// Linear part of the transform, from screen offsets to world offsets
+    pub fn transform(&self) -> Mat2 {
+        self.skew * Mat2::rotation(self.rotation)
+    }
+
+    /// World size of a pixel, before the skew
+    pub fn pixel_size(&self, size: Size) -> f64 {
+        2. * self.focus.radius / size.width.min(size.height)
+    }
+
+    /// World point of a screen point, in pixels from the top left corner
+    pub fn px_to_world(&self, size: Size, point: Point) -> Point {
+        let pixel_size = self.pixel_size(size);
+        let (x, y) = self.transform().apply(
+            (point.x - size.width / 2.) * pixel_size,
+            (point.y - size.height / 2.) * pixel_size,
+        );
+        Point::new(self.focus.center.x + x, self.focus.center.y + y)
+    }
+
+    /// Screen point of a world point, inverse of `px_to_world`
+    pub fn world_to_px(&self, size: Size, point: Point) -> Point {
+        let pixel_size = self.pixel_size(size);
+        let (x, y) = self
+            .transform()
+            .inverse()
+            .apply(point.x - self.focus.center.x, point.y - self.focus.center.y);
+        Point::new(
+            x / pixel_size + size.width / 2.,
+            y / pixel_size + size.height / 2.,
+        )
+    }
+
+    /// Zoom by `factor` keeping the world point under `pos` fixed on screen
+    pub fn zoom_at(&mut self, size: Size, pos: Point, factor: f64) {
+        let w = self.px_to_world(size, pos);
+        self.focus.center = w + (self.focus.center - w) / factor;
+        self.focus.radius /= factor;
+    }
+
+    /// Zoom into a screen rectangle, its shorter side filling the screen
+    pub fn zoom_rect(&mut self, size: Size, selection: Rect) {
+        self.focus.center = self.px_to_world(size, selection.center());
+        self.focus.radius *=
+            selection.width().min(selection.height()) / size.width.min(size.height);
+    }
+
+    /// Move by a fraction of the radius, along the screen axes
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        let (dx, dy) = self.transform().apply(dx, dy);
+        self.focus.center.x += dx * self.focus.radius;
+        self.focus.center.y += dy * self.focus.radius;
+    }
+
+    /// Move the world point under `from` to `to`
+    pub fn drag(&mut self, size: Size, from: Point, to: Point) {
+        self.focus.center += self.px_to_world(size, from) - self.px_to_world(size, to);
+    }
+
+    /// Transform drawing an image rendered with this viewport over the whole
+    /// screen, when the screen shows `view`
+    pub fn image_transform(&self, size: Size, view: &Viewport) -> Affine {
+        let scale = self.focus.radius / view.focus.radius;
+        let to_view = view.transform().inverse();
+        let offset = (self.focus.center - view.focus.center) / view.pixel_size(size);
+        let (offset_x, offset_y) = to_view.apply(offset.x, offset.y);
+        let m = to_view * self.transform();
+        let half = Vec2::new(size.width / 2., size.height / 2.);
+        Affine::translate(half + Vec2::new(offset_x, offset_y))
+            * Affine::new([m.a, m.c, m.b, m.d, 0., 0.])
+            * Affine::scale(scale)
+            * Affine::translate(-half)
+    }
+
+    /// Viewport at `t` on the way to `other`, zooming at constant speed and
+    /// turning the short way around
+    pub fn lerp(&self, other: &Viewport, t: f64) -> Self {
+        let (r0, r1) = (self.focus.radius, other.focus.radius);
+        let turn = (other.rotation - self.rotation + 180.).rem_euclid(360.) - 180.;
+        Viewport {
+            focus: Circle::new(
+                self.focus.center.lerp(other.focus.center, t),
+                (r0.ln() * (1. - t) + r1.ln() * t).exp(),
+            ),
+            rotation: self.rotation + turn * t,
+            skew: self.skew.lerp(&other.skew, t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const CASES: usize = 1000;
+    // Tolerance, in pixels
+    const EPS: f64 = 1e-3;
+
+    fn random_size(rng: &mut StdRng) -> Size {
+        Size::new(rng.gen_range(1..2000) as f64, rng.gen_range(1..2000) as f64)
+    }
+
+    fn random_view(rng: &mut StdRng) -> Viewport {
+        Viewport {
+            focus: Circle::new(
+                Point::new(rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0)),
+                10f64.powf(rng.gen_range(-8.0..1.0)),
+            ),
+            rotation: rng.gen_range(0.0..360.),
+            // Far from singular
+            skew: Mat2 {
+                a: rng.gen_range(0.5..2.),
+                b: rng.gen_range(-0.3..0.3),
+                c: rng.gen_range(-0.3..0.3),
+                d: rng.gen_range(0.5..2.),
+            },
+        }
+    }
+
+    fn random_px(rng: &mut StdRng, size: Size) -> Point {
+        Point::new(
+            rng.gen_range(0.0..size.width),
+            rng.gen_range(0.0..size.height),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..CASES {
+            let (view, size) = (random_view(&mut rng), random_size(&mut rng));
+            let p = random_px(&mut rng, size);
+            let w = view.px_to_world(size, p);
+            assert!(view.world_to_px(size, w).distance(p) < EPS);
+            let back = view.px_to_world(size, view.world_to_px(size, w));
+            assert!(back.distance(w) < EPS * 4. * view.pixel_size(size));
+        }
+    }
+
+    #[test]
+    fn aspect_ratio() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for case in 0..CASES {
+            let mut view = random_view(&mut rng);
+            view.skew = Mat2::IDENTITY;
+            let (short, long) = (
+                rng.gen_range(1..1000) as f64,
+                rng.gen_range(1000..2000) as f64,
+            );
+            // Landscape and portrait windows
+            let size = if case % 2 == 0 {
+                Size::new(long, short)
+            } else {
+                Size::new(short, long)
+            };
+            let world_dist = |a: Point, b: Point| {
+                view.px_to_world(size, a)
+                    .distance(view.px_to_world(size, b))
+            };
+            let (w, h) = (size.width, size.height);
+            let diameter = 2. * view.focus.radius;
+            let tolerance = EPS * view.pixel_size(size);
+            let width = world_dist(Point::new(0., h / 2.), Point::new(w, h / 2.));
+            let height = world_dist(Point::new(w / 2., 0.), Point::new(w / 2., h));
+            // The focus circle fits the shorter side, the longer one extends it
+            assert!((width.min(height) - diameter).abs() < tolerance);
+            assert!((width.max(height) - diameter * long / short).abs() < tolerance);
+            // Square pixels
+            let p = random_px(&mut rng, size);
+            let dx = world_dist(p, p + Vec2::new(1., 0.));
+            let dy = world_dist(p, p + Vec2::new(0., 1.));
+            assert!((dx - dy).abs() < tolerance);
+        }
+    }
+
+    #[test]
+    fn zoom_about_fixed_point() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..CASES {
+            let (mut view, size) = (random_view(&mut rng), random_size(&mut rng));
+            let pos = random_px(&mut rng, size);
+            let factor = rng.gen_range(-3.0f64..3.).exp();
+            let radius = view.focus.radius;
+            let w = view.px_to_world(size, pos);
+            view.zoom_at(size, pos, factor);
+            assert!(view.world_to_px(size, w).distance(pos) < EPS);
+            assert!((view.focus.radius * factor - radius).abs() < 1e-9 * radius);
+        }
+    }
+
+    #[test]
+    fn skew_lerp_stays_invertible() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let close = |m: Mat2, n: Mat2| {
+            (m.a - n.a).abs() + (m.b - n.b).abs() + (m.c - n.c).abs() + (m.d - n.d).abs() < 1e-9
+        };
+        for _ in 0..CASES {
+            let (mut m, mut n) = (random_view(&mut rng).skew, random_view(&mut rng).skew);
+            // Reflections and rotations on either end
+            m = Mat2::rotation(rng.gen_range(0.0..360.)) * m;
+            n = Mat2::rotation(rng.gen_range(0.0..360.)) * n;
+            if rng.gen() {
+                n = n * Mat2::FLIP_Y;
+            }
+            assert!(close(m.lerp(&n, 0.), m) && close(m.lerp(&n, 1.), n));
+            for i in 0..=20 {
+                assert!(m.lerp(&n, i as f64 / 20.).det().abs() > 1e-3);
+            }
+        }
+    }
+}